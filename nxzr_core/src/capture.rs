@@ -0,0 +1,595 @@
+use crate::protocol::{Transport, TransportPause, TransportRead, TransportWrite};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        mpsc::{self, RecvTimeoutError, TrySendError},
+        Arc,
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::watch;
+
+// Captures the reports exchanged with the host in the btsnoop format, so that
+// they can be inspected with Wireshark.
+//
+// Ref: https://www.fte.com/webhelpii/hsu/Content/Technical_Information/BT_Snoop_File_Format.htm
+
+// Datalink type of HCI UART (H4), each report is framed as an ACL packet.
+const BTSNOOP_DATALINK_H4: u32 = 1002;
+// Timestamps are in microseconds since midnight, January 1st, 0 AD, which is
+// the base used by Wireshark.
+const BTSNOOP_EPOCH_DELTA: u64 = 0x00DC_DDB3_0F2F_8000;
+const BTSNOOP_HEADER_SIZE: u64 = 16;
+
+const ACL_HANDLE: u16 = 0x000B;
+// L2CAP channels of the HID interrupt channel, the host is the initiator.
+const HID_INTERRUPT_PSM: u16 = 0x0013;
+const HOST_CID: u16 = 0x0040;
+const DEVICE_CID: u16 = 0x0041;
+const SIGNALING_CID: u16 = 0x0001;
+
+// Records are buffered, and flushed to the file at this interval.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+// Number of the records queued for the writer thread, the ones over it are
+// dropped and counted in the capture.
+const QUEUE_CAPACITY: usize = 1024;
+
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum CaptureError {
+    // Returned when the file is not a btsnoop capture written by this module.
+    #[error("malformed capture: {0}")]
+    Malformed(String),
+    #[error("capture writer has been closed")]
+    Closed,
+    #[error("io error: {message}")]
+    Io {
+        kind: std::io::ErrorKind,
+        message: String,
+    },
+}
+
+impl From<std::io::Error> for CaptureError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io {
+            kind: err.kind(),
+            message: err.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CaptureConfig {
+    // Path to the capture file. Rotated files are suffixed with a number,
+    // e.g. `nxzr.btsnoop.1` is the latest one.
+    pub path: PathBuf,
+    // The file is rotated when it grows over this size in bytes.
+    pub max_file_size: u64,
+    // Number of the rotated files to keep besides the current one.
+    pub max_files: usize,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("nxzr.btsnoop"),
+            max_file_size: 16 * 1024 * 1024,
+            max_files: 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Direction {
+    // Input reports sent to the host.
+    Sent,
+    // Output reports received from the host.
+    Received,
+}
+
+// A report recorded in the capture.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CaptureRecord {
+    pub direction: Direction,
+    pub timestamp: SystemTime,
+    pub report: Bytes,
+}
+
+// Reads the reports from the capture file, skipping the signaling packets.
+pub fn read_capture(path: impl AsRef<Path>) -> Result<Vec<CaptureRecord>, CaptureError> {
+    parse_capture(&fs::read(path)?)
+}
+
+pub fn parse_capture(buf: &[u8]) -> Result<Vec<CaptureRecord>, CaptureError> {
+    let malformed = |message: &str| CaptureError::Malformed(message.into());
+    if buf.len() < BTSNOOP_HEADER_SIZE as usize || !buf.starts_with(b"btsnoop\0") {
+        return Err(malformed("not a btsnoop file"));
+    }
+    if buf[12..16] != BTSNOOP_DATALINK_H4.to_be_bytes() {
+        return Err(malformed("unsupported datalink type"));
+    }
+    let mut records = vec![];
+    let mut rest = &buf[BTSNOOP_HEADER_SIZE as usize..];
+    while !rest.is_empty() {
+        let Some(header) = rest.get(..24) else {
+            return Err(malformed("truncated record header"));
+        };
+        let included_len = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+        let flags = u32::from_be_bytes(header[8..12].try_into().unwrap());
+        let micros = u64::from_be_bytes(header[16..24].try_into().unwrap());
+        let Some(packet) = rest.get(24..24 + included_len) else {
+            return Err(malformed("truncated record"));
+        };
+        rest = &rest[24 + included_len..];
+        // Only the ACL packets of the HID channel are of interest.
+        let [0x02, _, _, _, _, _, _, cid_lo, cid_hi, report @ ..] = packet else {
+            continue;
+        };
+        if u16::from_le_bytes([*cid_lo, *cid_hi]) == SIGNALING_CID {
+            continue;
+        }
+        records.push(CaptureRecord {
+            direction: if flags & 0x01 == 0 {
+                Direction::Sent
+            } else {
+                Direction::Received
+            },
+            timestamp: UNIX_EPOCH
+                + Duration::from_micros(micros.saturating_sub(BTSNOOP_EPOCH_DELTA)),
+            report: Bytes::copy_from_slice(report),
+        });
+    }
+    Ok(records)
+}
+
+// Writes the records to the capture file from a dedicated thread, so that
+// recording never blocks the transport. The buffered records are flushed at an
+// interval, on rotation, and when the writer is closed or dropped.
+//
+// Creating the writer touches the file system, so should be done off the async
+// runtime, e.g. with `tokio::task::spawn_blocking`.
+#[derive(Debug)]
+pub struct CaptureWriter {
+    tx: Option<mpsc::SyncSender<CaptureCommand>>,
+    thread: Option<thread::JoinHandle<()>>,
+    dropped: Arc<AtomicU32>,
+    error_tx: Arc<watch::Sender<Option<CaptureError>>>,
+}
+
+#[derive(Debug)]
+enum CaptureCommand {
+    Record {
+        direction: Direction,
+        report: Bytes,
+        timestamp: SystemTime,
+    },
+    Flush(mpsc::SyncSender<Result<(), CaptureError>>),
+}
+
+impl CaptureWriter {
+    // Creates a new capture file, the existing one is rotated.
+    pub fn create(config: CaptureConfig) -> Result<Self, CaptureError> {
+        rotate(&config)?;
+        let file = CaptureFile::create(&config.path)?;
+        let (tx, rx) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let dropped = Arc::new(AtomicU32::new(0));
+        let error_tx = Arc::new(watch::channel(None).0);
+        let mut sink = CaptureSink {
+            config,
+            file,
+            dropped: dropped.clone(),
+            error_tx: error_tx.clone(),
+        };
+        let thread = thread::Builder::new()
+            .name("nxzr-capture".into())
+            .spawn(move || sink.run(rx))?;
+        Ok(Self {
+            tx: Some(tx),
+            thread: Some(thread),
+            dropped,
+            error_tx,
+        })
+    }
+
+    pub fn record(&self, direction: Direction, report: &[u8]) -> Result<(), CaptureError> {
+        self.record_at(direction, report, SystemTime::now())
+    }
+
+    // Queues the record without waiting for it to be written. Returns the
+    // error of the writer if it has failed, after which nothing is recorded.
+    pub fn record_at(
+        &self,
+        direction: Direction,
+        report: &[u8],
+        timestamp: SystemTime,
+    ) -> Result<(), CaptureError> {
+        if let Some(err) = self.error() {
+            return Err(err);
+        }
+        let Some(tx) = &self.tx else {
+            return Err(CaptureError::Closed);
+        };
+        match tx.try_send(CaptureCommand::Record {
+            direction,
+            report: Bytes::copy_from_slice(report),
+            timestamp,
+        }) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => Err(CaptureError::Closed),
+        }
+    }
+
+    // Blocks until the records queued so far are written to the file.
+    pub fn flush(&self) -> Result<(), CaptureError> {
+        let Some(tx) = &self.tx else {
+            return Err(CaptureError::Closed);
+        };
+        let (ack_tx, ack_rx) = mpsc::sync_channel(1);
+        tx.send(CaptureCommand::Flush(ack_tx))
+            .map_err(|_| CaptureError::Closed)?;
+        ack_rx.recv().map_err(|_| CaptureError::Closed)?
+    }
+
+    // Blocks until the thread has written the rest and exited, returning the
+    // error of the writer if any.
+    pub fn close(mut self) -> Result<(), CaptureError> {
+        drop(self.tx.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        match self.error() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    // The first error of the writer, e.g. when the disk is full.
+    pub fn error(&self) -> Option<CaptureError> {
+        self.error_tx.borrow().clone()
+    }
+
+    // Watch the first error of the writer, which is set only once.
+    pub fn watch_error(&self) -> watch::Receiver<Option<CaptureError>> {
+        self.error_tx.subscribe()
+    }
+}
+
+impl Drop for CaptureWriter {
+    fn drop(&mut self) {
+        // Disconnects the thread, which flushes the rest before exiting. It is
+        // detached rather than joined, as the writer may be dropped on the
+        // async runtime.
+        drop(self.tx.take());
+    }
+}
+
+// Owned by the writer thread.
+struct CaptureSink {
+    config: CaptureConfig,
+    file: CaptureFile,
+    dropped: Arc<AtomicU32>,
+    error_tx: Arc<watch::Sender<Option<CaptureError>>>,
+}
+
+impl CaptureSink {
+    fn run(&mut self, rx: mpsc::Receiver<CaptureCommand>) {
+        loop {
+            let res = match rx.recv_timeout(FLUSH_INTERVAL) {
+                Ok(CaptureCommand::Record {
+                    direction,
+                    report,
+                    timestamp,
+                }) => self.record(direction, &report, timestamp),
+                Ok(CaptureCommand::Flush(ack_tx)) => {
+                    let res = self.flush();
+                    let _ = ack_tx.send(res.clone());
+                    res
+                }
+                Err(RecvTimeoutError::Timeout) => self.flush(),
+                Err(RecvTimeoutError::Disconnected) => {
+                    let _ = self.flush();
+                    return;
+                }
+            };
+            if let Err(err) = res {
+                self.error_tx.send_if_modified(|error| {
+                    if error.is_some() {
+                        return false;
+                    }
+                    *error = Some(err);
+                    true
+                });
+            }
+        }
+    }
+
+    fn record(
+        &mut self,
+        direction: Direction,
+        report: &[u8],
+        timestamp: SystemTime,
+    ) -> Result<(), CaptureError> {
+        // The file may be left broken in the middle of a record.
+        if let Some(err) = self.error_tx.borrow().clone() {
+            return Err(err);
+        }
+        if self.file.size >= self.config.max_file_size {
+            self.file.writer.flush()?;
+            rotate(&self.config)?;
+            self.file = CaptureFile::create(&self.config.path)?;
+        }
+        let cid = match direction {
+            Direction::Sent => HOST_CID,
+            Direction::Received => DEVICE_CID,
+        };
+        self.file.write_record(&encode_record(
+            direction,
+            &encode_acl(cid, report),
+            timestamp,
+            self.dropped.load(Ordering::Relaxed),
+        ))
+    }
+
+    fn flush(&mut self) -> Result<(), CaptureError> {
+        if let Some(err) = self.error_tx.borrow().clone() {
+            return Err(err);
+        }
+        Ok(self.file.writer.flush()?)
+    }
+}
+
+#[derive(Debug)]
+struct CaptureFile {
+    writer: BufWriter<File>,
+    size: u64,
+}
+
+impl CaptureFile {
+    fn create(path: &Path) -> Result<Self, CaptureError> {
+        let mut file = Self {
+            writer: BufWriter::new(File::create(path)?),
+            size: 0,
+        };
+        file.write_record(&encode_header())?;
+        // Opens the HID interrupt channel first, so that Wireshark decodes the
+        // reports as HID.
+        let timestamp = SystemTime::now();
+        let mut request = vec![0x02, 0x01, 0x04, 0x00];
+        request.extend_from_slice(&HID_INTERRUPT_PSM.to_le_bytes());
+        request.extend_from_slice(&HOST_CID.to_le_bytes());
+        file.write_record(&encode_record(
+            Direction::Received,
+            &encode_acl(SIGNALING_CID, &request),
+            timestamp,
+            0,
+        ))?;
+        let mut response = vec![0x03, 0x01, 0x08, 0x00];
+        response.extend_from_slice(&DEVICE_CID.to_le_bytes());
+        response.extend_from_slice(&HOST_CID.to_le_bytes());
+        response.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        file.write_record(&encode_record(
+            Direction::Sent,
+            &encode_acl(SIGNALING_CID, &response),
+            timestamp,
+            0,
+        ))?;
+        Ok(file)
+    }
+
+    fn write_record(&mut self, buf: &[u8]) -> Result<(), CaptureError> {
+        self.writer.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(())
+    }
+}
+
+// Shifts the rotated files by one, dropping the oldest one.
+fn rotate(config: &CaptureConfig) -> Result<(), CaptureError> {
+    let rotated_path = |n: usize| {
+        let mut path = config.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        PathBuf::from(path)
+    };
+    if !config.path.exists() {
+        return Ok(());
+    }
+    if config.max_files == 0 {
+        fs::remove_file(&config.path)?;
+        return Ok(());
+    }
+    for n in (1..config.max_files).rev() {
+        let from = rotated_path(n);
+        if from.exists() {
+            fs::rename(from, rotated_path(n + 1))?;
+        }
+    }
+    fs::rename(&config.path, rotated_path(1))?;
+    Ok(())
+}
+
+fn encode_header() -> Vec<u8> {
+    let mut buf = Vec::with_capacity(BTSNOOP_HEADER_SIZE as usize);
+    buf.extend_from_slice(b"btsnoop\0");
+    buf.extend_from_slice(&1u32.to_be_bytes());
+    buf.extend_from_slice(&BTSNOOP_DATALINK_H4.to_be_bytes());
+    buf
+}
+
+// Encodes the L2CAP payload as an H4 ACL packet.
+fn encode_acl(cid: u16, payload: &[u8]) -> Vec<u8> {
+    let l2cap_len = payload.len() as u16;
+    let mut buf = Vec::with_capacity(payload.len() + 9);
+    buf.push(0x02);
+    // Packet boundary flag: first automatically flushable packet.
+    buf.extend_from_slice(&(ACL_HANDLE | 0x2000).to_le_bytes());
+    buf.extend_from_slice(&(l2cap_len + 4).to_le_bytes());
+    buf.extend_from_slice(&l2cap_len.to_le_bytes());
+    buf.extend_from_slice(&cid.to_le_bytes());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+// Record format:
+//
+// Original length (4) | Included length (4) | Flags (4) | Drops (4) | Timestamp (8)
+fn encode_record(
+    direction: Direction,
+    packet: &[u8],
+    timestamp: SystemTime,
+    drops: u32,
+) -> Vec<u8> {
+    let micros = timestamp
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_micros() as u64)
+        .unwrap_or_default();
+    let flags: u32 = match direction {
+        Direction::Sent => 0x00,
+        Direction::Received => 0x01,
+    };
+    let mut buf = Vec::with_capacity(packet.len() + 24);
+    buf.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+    buf.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+    buf.extend_from_slice(&flags.to_be_bytes());
+    buf.extend_from_slice(&drops.to_be_bytes());
+    buf.extend_from_slice(&(micros + BTSNOOP_EPOCH_DELTA).to_be_bytes());
+    buf.extend_from_slice(packet);
+    buf
+}
+
+// Transport wrapper which records every report passing through.
+//
+// Failing to record does not fail the transport, since capturing must not
+// affect the connection. The error is surfaced through
+// `CaptureWriter::watch_error` instead.
+#[derive(Debug, Clone)]
+pub struct CaptureTransport<T> {
+    transport: T,
+    writer: Arc<CaptureWriter>,
+}
+
+impl<T> CaptureTransport<T> {
+    pub fn new(transport: T, writer: Arc<CaptureWriter>) -> Self {
+        Self { transport, writer }
+    }
+}
+
+impl<T: Transport> Transport for CaptureTransport<T> {}
+
+#[async_trait]
+impl<T: TransportRead + Send + Sync> TransportRead for CaptureTransport<T> {
+    async fn read(&self) -> std::io::Result<BytesMut> {
+        let buf = self.transport.read().await?;
+        let _ = self.writer.record(Direction::Received, &buf);
+        Ok(buf)
+    }
+}
+
+#[async_trait]
+impl<T: TransportWrite + Send + Sync> TransportWrite for CaptureTransport<T> {
+    async fn write(&self, buf: Bytes) -> std::io::Result<()> {
+        let _ = self.writer.record(Direction::Sent, &buf);
+        self.transport.write(buf).await
+    }
+}
+
+impl<T: TransportPause> TransportPause for CaptureTransport<T> {
+    fn pause(&self) {
+        self.transport.pause()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_capture, read_capture, CaptureConfig, CaptureError, CaptureWriter, Direction,
+    };
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn write_btsnoop_records() {
+        let dir = std::env::temp_dir().join(format!("nxzr_capture_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = CaptureConfig {
+            path: dir.join("capture.btsnoop"),
+            max_file_size: 200,
+            max_files: 2,
+        };
+        let writer = CaptureWriter::create(config.clone()).unwrap();
+        let timestamp = UNIX_EPOCH + Duration::from_secs(1);
+        writer
+            .record_at(Direction::Received, &[0xA2, 0x01], timestamp)
+            .unwrap();
+        writer.flush().unwrap();
+
+        let buf = std::fs::read(&config.path).unwrap();
+        assert_eq!(&buf[..16], b"btsnoop\0\x00\x00\x00\x01\x00\x00\x03\xEA");
+        // Header, and the signaling packets opening the channel.
+        let record = &buf[16 + 24 + 17 + 24 + 21..];
+        // Lengths, flags and drops
+        assert_eq!(
+            record[..16],
+            [0, 0, 0, 0x0B, 0, 0, 0, 0x0B, 0, 0, 0, 0x01, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            record[16..24],
+            [0x00, 0xDC, 0xDD, 0xB3, 0x0F, 0x3E, 0xC2, 0x40]
+        );
+        // ACL and L2CAP headers, followed by the report.
+        assert_eq!(
+            record[24..],
+            [0x02, 0x0B, 0x20, 0x06, 0x00, 0x02, 0x00, 0x41, 0x00, 0xA2, 0x01]
+        );
+
+        let records = read_capture(&config.path).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].direction, Direction::Received);
+        assert_eq!(records[0].timestamp, timestamp);
+        assert_eq!(&records[0].report[..], &[0xA2, 0x01]);
+        assert!(parse_capture(&buf[..buf.len() - 1]).is_err());
+
+        // Rotates when the file is full, keeping the latest ones.
+        for _ in 0..6 {
+            writer.record(Direction::Sent, &[0xA1; 49]).unwrap();
+        }
+        writer.flush().unwrap();
+        assert!(config.path.exists());
+        assert!(dir.join("capture.btsnoop.1").exists());
+        assert!(dir.join("capture.btsnoop.2").exists());
+        assert!(!dir.join("capture.btsnoop.3").exists());
+
+        // Closing the writer flushes the rest.
+        writer.record(Direction::Sent, &[0xA1; 49]).unwrap();
+        writer.close().unwrap();
+        let records = read_capture(&config.path).unwrap();
+        assert_eq!(records.len(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn surface_write_errors() {
+        let dir = std::env::temp_dir().join(format!("nxzr_capture_error_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let writer = CaptureWriter::create(CaptureConfig {
+            path: dir.join("capture.btsnoop"),
+            max_file_size: 0,
+            max_files: 1,
+        })
+        .unwrap();
+        let error_rx = writer.watch_error();
+        // Rotation fails to create the new file.
+        std::fs::remove_dir_all(&dir).unwrap();
+        writer.record(Direction::Sent, &[0xA1; 49]).unwrap();
+        assert!(matches!(writer.flush(), Err(CaptureError::Io { .. })));
+        assert!(error_rx.borrow().is_some());
+        assert!(writer.record(Direction::Sent, &[0xA1; 49]).is_err());
+    }
+}
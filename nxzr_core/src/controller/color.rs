@@ -0,0 +1,156 @@
+use std::{fmt, str::FromStr};
+
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum ColorError {
+    #[error("invalid color format, expected hex notation like \"#RRGGBB\": {0}")]
+    InvalidFormat(String),
+}
+
+#[derive(Clone, Copy, Default, Debug, Eq, PartialEq, Hash)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    // Creates a color from the `0xRRGGBB` form, the most significant byte is ignored.
+    pub const fn from_u32(value: u32) -> Self {
+        Self {
+            r: (value >> 16) as u8,
+            g: (value >> 8) as u8,
+            b: value as u8,
+        }
+    }
+
+    pub const fn to_u32(&self) -> u32 {
+        (self.r as u32) << 16 | (self.g as u32) << 8 | self.b as u32
+    }
+
+    pub fn to_bytes(&self) -> [u8; 3] {
+        [self.r, self.g, self.b]
+    }
+
+    pub fn with_bytes(bytes: &[u8]) -> Option<Self> {
+        let &[r, g, b] = bytes.get(..3)? else {
+            return None;
+        };
+        Some(Self { r, g, b })
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}", self.r, self.g, self.b)
+    }
+}
+
+impl FromStr for Rgb {
+    type Err = ColorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if hex.len() != 6 {
+            return Err(ColorError::InvalidFormat(s.to_string()));
+        }
+        let value =
+            u32::from_str_radix(hex, 16).map_err(|_| ColorError::InvalidFormat(s.to_string()))?;
+        Ok(Self::from_u32(value))
+    }
+}
+
+// Colors of the controller shown on the Switch, stored in the SPI flash.
+//
+// Ref: https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/spi_flash_notes.md#x6000-factory-configuration-and-calibration
+#[derive(Clone, Copy, Default, Debug, Eq, PartialEq, Hash)]
+pub struct ControllerColors {
+    pub body: Rgb,
+    pub buttons: Rgb,
+    // Grip colors are only used by the Pro Controller.
+    pub left_grip: Option<Rgb>,
+    pub right_grip: Option<Rgb>,
+}
+
+impl ControllerColors {
+    pub fn new(body: Rgb, buttons: Rgb) -> Self {
+        Self {
+            body,
+            buttons,
+            left_grip: None,
+            right_grip: None,
+        }
+    }
+
+    pub fn has_grip_colors(&self) -> bool {
+        self.left_grip.is_some() || self.right_grip.is_some()
+    }
+
+    // Encodes to the 12 bytes layout of the 0x6050 region.
+    pub fn to_bytes(&self) -> [u8; 12] {
+        let mut buf = [0xFF; 12];
+        buf[0..3].copy_from_slice(&self.body.to_bytes());
+        buf[3..6].copy_from_slice(&self.buttons.to_bytes());
+        if let Some(left_grip) = self.left_grip {
+            buf[6..9].copy_from_slice(&left_grip.to_bytes());
+        }
+        if let Some(right_grip) = self.right_grip {
+            buf[9..12].copy_from_slice(&right_grip.to_bytes());
+        }
+        buf
+    }
+
+    pub fn with_bytes(bytes: &[u8], has_grip_colors: bool) -> Option<Self> {
+        if bytes.len() < 12 {
+            return None;
+        }
+        Some(Self {
+            body: Rgb::with_bytes(&bytes[0..3])?,
+            buttons: Rgb::with_bytes(&bytes[3..6])?,
+            left_grip: has_grip_colors
+                .then(|| Rgb::with_bytes(&bytes[6..9]))
+                .flatten(),
+            right_grip: has_grip_colors
+                .then(|| Rgb::with_bytes(&bytes[9..12]))
+                .flatten(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ControllerColors, Rgb};
+    use std::str::FromStr;
+
+    #[test]
+    fn parse_rgb() {
+        assert_eq!(
+            Rgb::from_str("#BC1142").unwrap(),
+            Rgb::new(0xBC, 0x11, 0x42)
+        );
+        assert_eq!(Rgb::from_str("75a928").unwrap(), Rgb::new(0x75, 0xA9, 0x28));
+        assert!(Rgb::from_str("#12345").is_err());
+        assert!(Rgb::from_str("#GGGGGG").is_err());
+        assert_eq!(Rgb::from_u32(0x323232).to_string(), "#323232");
+    }
+
+    #[test]
+    fn controller_colors_bytes() {
+        let colors = ControllerColors {
+            left_grip: Some(Rgb::from_u32(0x0A0B0C)),
+            ..ControllerColors::new(Rgb::from_u32(0x323232), Rgb::from_u32(0xFFFFFF))
+        };
+        let bytes = colors.to_bytes();
+        assert_eq!(
+            bytes,
+            [0x32, 0x32, 0x32, 0xFF, 0xFF, 0xFF, 0x0A, 0x0B, 0x0C, 0xFF, 0xFF, 0xFF]
+        );
+        let decoded = ControllerColors::with_bytes(&bytes, true).unwrap();
+        assert_eq!(decoded.body, colors.body);
+        assert_eq!(decoded.left_grip, colors.left_grip);
+        assert_eq!(decoded.right_grip, Some(Rgb::from_u32(0xFFFFFF)));
+    }
+}
@@ -0,0 +1,57 @@
+use super::{
+    protocol::ControllerProtocolError,
+    report::{input::InputReport, subcommand::SubcommandId},
+};
+use std::fmt::Debug;
+
+// What the protocol should do after the subcommand handler is called.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub enum SubcommandReply {
+    // Continues with the built-in handler, or the fallback policy if the
+    // subcommand is not supported.
+    #[default]
+    Default,
+    // Sends the input report as built by the handler.
+    Reply,
+    // Sends nothing back to the host.
+    Ignore,
+}
+
+// How to reply to the subcommands not supported by the protocol nor the
+// handler.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub enum UnsupportedSubcommandPolicy {
+    // Replies with a plain ACK without any data, which keeps the host going
+    // in most cases.
+    #[default]
+    GenericAck,
+    // Sends nothing back, which may stall the host.
+    Ignore,
+}
+
+// Custom handler for the subcommands sent by the host, registered through
+// `ControllerProtocolConfig`.
+//
+// The input report given to the handler is a 0x21 report with the current
+// controller state, and the subcommand reply is left blank.
+pub trait SubcommandHandler: Debug + Send + Sync {
+    // Called before the built-in handler, overrides the reply by returning
+    // other than `SubcommandReply::Default`.
+    fn handle(
+        &self,
+        id: SubcommandId,
+        data: &[u8],
+        input_report: &mut InputReport,
+    ) -> Result<SubcommandReply, ControllerProtocolError>;
+
+    // Called after the built-in handler has built the reply, which can be
+    // extended before sent.
+    fn extend(
+        &self,
+        _id: SubcommandId,
+        _data: &[u8],
+        _input_report: &mut InputReport,
+    ) -> Result<(), ControllerProtocolError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+use super::state::imu::{AccelSensitivity, GyroSensitivity};
+
+// Emulated register file of the LSM6DS3 6-axis sensor, accessed by the host
+// through `WriteTo6AxisRegisters` and `Read6AxisRegisters` subcommands.
+//
+// Ref: https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/bluetooth_hid_subcommands_notes.md#subcommand-0x42-6-axis-sensor-write-to-registers
+pub const IMU_REGISTERS_SIZE: usize = 0x80;
+// Maximum number of registers that can be read at once.
+pub const IMU_REGISTERS_MAX_READ: u8 = 0x20;
+
+const WHO_AM_I: usize = 0x0F;
+// Accelerometer control register: ODR_XL[7:4] | FS_XL[3:2] | BW_XL[1:0]
+const CTRL1_XL: usize = 0x10;
+// Gyroscope control register: ODR_G[7:4] | FS_G[3:2] | FS_125[1]
+const CTRL2_G: usize = 0x11;
+// Gyroscope high performance mode: G_HM_MODE[7]
+const CTRL7_G: usize = 0x16;
+// Accelerometer high performance mode and low pass filter: LPF2_XL_EN[7]
+const CTRL8_XL: usize = 0x17;
+
+#[derive(Clone, Debug)]
+pub struct ImuRegisters {
+    buf: [u8; IMU_REGISTERS_SIZE],
+}
+
+impl Default for ImuRegisters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ImuRegisters {
+    pub fn new() -> Self {
+        let mut buf = [0u8; IMU_REGISTERS_SIZE];
+        buf[WHO_AM_I] = 0x69;
+        // 416Hz, ±8g / ±2000dps, which is what the controller uses by default.
+        buf[CTRL1_XL] = 0x6C;
+        buf[CTRL2_G] = 0x6C;
+        Self { buf }
+    }
+
+    pub fn read(&self, address: u8, count: u8) -> Option<&[u8]> {
+        if count > IMU_REGISTERS_MAX_READ {
+            return None;
+        }
+        let start = usize::from(address);
+        self.buf.get(start..start + usize::from(count))
+    }
+
+    pub fn write(&mut self, address: u8, value: u8) -> Option<()> {
+        let register = self.buf.get_mut(usize::from(address))?;
+        *register = value;
+        Some(())
+    }
+
+    // Applies the arguments of `Set6AxisSensitivity` to the control registers.
+    // Returns `None` if any of the arguments is unknown.
+    pub fn set_sensitivity(&mut self, args: [u8; 4]) -> Option<()> {
+        let [gyro, accel, gyro_performance, accel_filter] = args;
+        let gyro_fs = match gyro {
+            0x00 => 0b00,
+            0x01 => 0b01,
+            0x02 => 0b10,
+            0x03 => 0b11,
+            _ => return None,
+        };
+        let accel_fs = match accel {
+            0x00 => 0b11,
+            0x01 => 0b10,
+            0x02 => 0b00,
+            0x03 => 0b01,
+            _ => return None,
+        };
+        // 0x00 = 833Hz (high performance), 0x01 = 208Hz
+        let gyro_odr = match gyro_performance {
+            0x00 => 0x7,
+            0x01 => 0x5,
+            _ => return None,
+        };
+        // 0x00 = 200Hz, 0x01 = 100Hz
+        let accel_bw = match accel_filter {
+            0x00 => 0b01,
+            0x01 => 0b10,
+            _ => return None,
+        };
+        self.buf[CTRL1_XL] = (self.buf[CTRL1_XL] & 0xF0) | accel_fs << 2 | accel_bw;
+        self.buf[CTRL2_G] = gyro_odr << 4 | gyro_fs << 2;
+        self.buf[CTRL7_G] &= 0x7F;
+        self.buf[CTRL8_XL] &= 0x7F;
+        Some(())
+    }
+
+    pub fn accel_sensitivity(&self) -> AccelSensitivity {
+        match (self.buf[CTRL1_XL] >> 2) & 0b11 {
+            0b00 => AccelSensitivity::G2,
+            0b01 => AccelSensitivity::G16,
+            0b10 => AccelSensitivity::G4,
+            _ => AccelSensitivity::G8,
+        }
+    }
+
+    pub fn gyro_sensitivity(&self) -> GyroSensitivity {
+        match (self.buf[CTRL2_G] >> 2) & 0b11 {
+            0b00 => GyroSensitivity::Dps250,
+            0b01 => GyroSensitivity::Dps500,
+            0b10 => GyroSensitivity::Dps1000,
+            _ => GyroSensitivity::Dps2000,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImuRegisters, CTRL1_XL, CTRL2_G};
+    use crate::controller::state::imu::{AccelSensitivity, GyroSensitivity};
+
+    #[test]
+    fn default_sensitivity() {
+        let registers = ImuRegisters::new();
+        assert_eq!(registers.accel_sensitivity(), AccelSensitivity::G8);
+        assert_eq!(registers.gyro_sensitivity(), GyroSensitivity::Dps2000);
+        assert_eq!(registers.read(0x0F, 1), Some(&[0x69][..]));
+    }
+
+    #[test]
+    fn set_sensitivity() {
+        let mut registers = ImuRegisters::new();
+        registers.set_sensitivity([0x00, 0x02, 0x01, 0x01]).unwrap();
+        assert_eq!(registers.accel_sensitivity(), AccelSensitivity::G2);
+        assert_eq!(registers.gyro_sensitivity(), GyroSensitivity::Dps250);
+        assert!(registers
+            .set_sensitivity([0x04, 0x00, 0x00, 0x00])
+            .is_none());
+        // Unchanged on failure.
+        assert_eq!(registers.gyro_sensitivity(), GyroSensitivity::Dps250);
+    }
+
+    #[test]
+    fn write_and_read_registers() {
+        let mut registers = ImuRegisters::new();
+        // FS_XL = ±4g, FS_G = ±1000dps
+        registers.write(CTRL1_XL as u8, 0x68).unwrap();
+        registers.write(CTRL2_G as u8, 0x68).unwrap();
+        assert_eq!(registers.accel_sensitivity(), AccelSensitivity::G4);
+        assert_eq!(registers.gyro_sensitivity(), GyroSensitivity::Dps1000);
+        assert_eq!(registers.read(CTRL1_XL as u8, 2), Some(&[0x68, 0x68][..]));
+        assert!(registers.write(0x80, 0x00).is_none());
+        assert!(registers.read(0x70, 0x11).is_none());
+        assert!(registers.read(0x00, 0x21).is_none());
+    }
+}
@@ -1,22 +1,65 @@
+use super::ControllerType;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default)]
+pub struct SendIntervalConfig {
+    // Interval of the full input reports (0x30, 0x31), which overrides the
+    // pace of the controller type if supplied, e.g. `1 / 60` seconds to keep
+    // the Pro Controller at 60hz over weak links. Must be greater than zero.
+    pub full_report_interval: Option<Duration>,
+}
+
+impl SendIntervalConfig {
+    pub fn is_valid(&self) -> bool {
+        !matches!(self.full_report_interval, Some(interval) if interval.is_zero())
+    }
+}
+
 #[derive(Debug, Clone)]
-pub struct SendInterval(pub Option<u8>);
+pub struct SendInterval {
+    mode: Option<u8>,
+    controller_type: ControllerType,
+    full_report_interval: Option<f64>,
+}
 
 impl SendInterval {
-    pub fn new(mode: Option<u8>) -> Self {
-        Self(mode)
+    pub fn new(mode: Option<u8>, controller_type: ControllerType) -> Self {
+        Self::with_config(mode, controller_type, &Default::default())
+    }
+
+    pub fn with_config(
+        mode: Option<u8>,
+        controller_type: ControllerType,
+        config: &SendIntervalConfig,
+    ) -> Self {
+        Self {
+            mode,
+            controller_type,
+            full_report_interval: config
+                .full_report_interval
+                .map(|interval| interval.as_secs_f64()),
+        }
     }
 
     pub fn to_byte(&self) -> Option<f64> {
-        let Some(mode) = self.0 else {
+        let Some(mode) = self.mode else {
             // For initial interval when no `mode` specified or subcommands replies
             return Some(f64::INFINITY);
         };
         match mode {
             0x3F => Some(1.0),
             0x21 => Some(f64::INFINITY),
-            // FIXME: revisit later for freq adjustment on procon 1/120
-            0x30 => Some(1.0 / 60.0),
-            0x31 => Some(1.0 / 60.0),
+            // The Pro Controller pushes the standard full reports at 120hz,
+            // while the Joy-Cons do at 60hz.
+            0x30 => Some(
+                self.full_report_interval
+                    .unwrap_or(match self.controller_type {
+                        ControllerType::ProController => 1.0 / 120.0,
+                        ControllerType::JoyConL | ControllerType::JoyConR => 1.0 / 60.0,
+                    }),
+            ),
+            // Large NFC/IR packets are pushed at 60hz for all controllers.
+            0x31 => Some(self.full_report_interval.unwrap_or(1.0 / 60.0)),
             // Unknown `mode` should be handled by caller and resort to the default interval.
             _ => None,
         }
@@ -26,3 +69,70 @@ impl SendInterval {
         1.0 / 15.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{SendInterval, SendIntervalConfig};
+    use crate::controller::{
+        protocol::{ControllerProtocol, ControllerProtocolConfig},
+        ControllerType,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn interval_for_mode_and_type() {
+        let table = [
+            (None, [f64::INFINITY; 3]),
+            (Some(0x3F), [1.0; 3]),
+            (Some(0x21), [f64::INFINITY; 3]),
+            (Some(0x30), [1.0 / 60.0, 1.0 / 60.0, 1.0 / 120.0]),
+            (Some(0x31), [1.0 / 60.0; 3]),
+        ];
+        let types = [
+            ControllerType::JoyConL,
+            ControllerType::JoyConR,
+            ControllerType::ProController,
+        ];
+        for (mode, intervals) in table {
+            for (controller_type, interval) in types.into_iter().zip(intervals) {
+                assert_eq!(
+                    SendInterval::new(mode, controller_type).to_byte(),
+                    Some(interval),
+                    "mode: {mode:?}, type: {controller_type}"
+                );
+            }
+        }
+        for controller_type in types {
+            assert_eq!(
+                SendInterval::new(Some(0x3E), controller_type).to_byte(),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn override_full_report_interval() {
+        let config = SendIntervalConfig {
+            full_report_interval: Some(Duration::from_secs_f64(1.0 / 60.0)),
+        };
+        assert!(config.is_valid());
+        let interval = |mode| {
+            SendInterval::with_config(Some(mode), ControllerType::ProController, &config).to_byte()
+        };
+        assert!((interval(0x30).unwrap() - 1.0 / 60.0).abs() < 1e-9);
+        assert!((interval(0x31).unwrap() - 1.0 / 60.0).abs() < 1e-9);
+        // Other modes are left as is.
+        assert_eq!(interval(0x3F), Some(1.0));
+        assert_eq!(interval(0x21), Some(f64::INFINITY));
+
+        let config = SendIntervalConfig {
+            full_report_interval: Some(Duration::ZERO),
+        };
+        assert!(!config.is_valid());
+        assert!(ControllerProtocol::new(ControllerProtocolConfig {
+            send_interval: config,
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,284 @@
+// IR camera emulation, which streams the frames pushed by the application as
+// image fragments through the NFC/IR MCU.
+//
+// Ref: https://github.com/CTCaer/jc_toolkit/blob/5.2.0/jctool/jctool.cpp
+
+// Size of the image data carried by a single fragment.
+pub const IR_FRAGMENT_SIZE: usize = 300;
+
+// Registers are addressed by (page, register).
+const RESOLUTION_REGISTER: (u8, u8) = (0x00, 0x2E);
+
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum IrCameraError {
+    // Returned when the frame data does not match with the given size.
+    #[error("invalid frame size: {0} bytes, expected {1} bytes")]
+    InvalidFrameSize(usize, usize),
+    #[error("unknown resolution register value: {0:#X}")]
+    UnknownResolution(u8),
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub enum IrResolution {
+    R320x240,
+    R160x120,
+    R80x60,
+    #[default]
+    R40x30,
+}
+
+impl IrResolution {
+    // Value of the resolution register.
+    pub fn from_register(value: u8) -> Option<Self> {
+        match value {
+            0x00 => Some(Self::R320x240),
+            0x50 => Some(Self::R160x120),
+            0x64 => Some(Self::R80x60),
+            0x69 => Some(Self::R40x30),
+            _ => None,
+        }
+    }
+
+    pub fn to_register(&self) -> u8 {
+        match self {
+            Self::R320x240 => 0x00,
+            Self::R160x120 => 0x50,
+            Self::R80x60 => 0x64,
+            Self::R40x30 => 0x69,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        match self {
+            Self::R320x240 => 320,
+            Self::R160x120 => 160,
+            Self::R80x60 => 80,
+            Self::R40x30 => 40,
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        self.width() * 3 / 4
+    }
+
+    pub fn fragment_count(&self) -> usize {
+        self.width() * self.height() / IR_FRAGMENT_SIZE
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub enum IrMode {
+    #[default]
+    Ready,
+    ImageTransfer,
+    // Other modes such as clustering are not supported.
+    Unsupported(u8),
+}
+
+impl IrMode {
+    pub fn from_byte(byte: u8) -> Self {
+        match byte {
+            0x02 => Self::Ready,
+            0x07 => Self::ImageTransfer,
+            byte => Self::Unsupported(byte),
+        }
+    }
+}
+
+// 8-bit grayscale frame in row-major order.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IrFrame {
+    width: usize,
+    height: usize,
+    data: Vec<u8>,
+}
+
+impl IrFrame {
+    pub fn new(width: usize, height: usize, data: Vec<u8>) -> Result<Self, IrCameraError> {
+        if data.len() != width * height || data.is_empty() {
+            return Err(IrCameraError::InvalidFrameSize(data.len(), width * height));
+        }
+        Ok(Self {
+            width,
+            height,
+            data,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    // Scales to the given resolution with the nearest neighbor.
+    fn resample(&self, resolution: IrResolution) -> Vec<u8> {
+        let (width, height) = (resolution.width(), resolution.height());
+        let mut buf = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let src_y = y * self.height / height;
+            for x in 0..width {
+                let src_x = x * self.width / width;
+                buf.push(self.data[src_y * self.width + src_x]);
+            }
+        }
+        buf
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct IrCamera {
+    mode: IrMode,
+    resolution: IrResolution,
+    // The latest frame pushed by the application.
+    frame: Option<IrFrame>,
+    // The frame being transferred, resampled to the resolution.
+    transfer: Vec<u8>,
+    fragment: usize,
+    // Whether the current fragment has been sent, so that it can be acknowledged.
+    is_sent: bool,
+}
+
+impl IrCamera {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn mode(&self) -> IrMode {
+        self.mode
+    }
+
+    pub fn resolution(&self) -> IrResolution {
+        self.resolution
+    }
+
+    pub fn set_mode(&mut self, mode: IrMode) {
+        self.mode = mode;
+        self.reset_transfer();
+    }
+
+    // Writes the registers given as (page, register, value) tuples. Registers
+    // other than the resolution are ignored.
+    pub fn write_registers(&mut self, registers: &[(u8, u8, u8)]) -> Result<(), IrCameraError> {
+        for &(page, register, value) in registers {
+            if (page, register) != RESOLUTION_REGISTER {
+                continue;
+            }
+            let Some(resolution) = IrResolution::from_register(value) else {
+                return Err(IrCameraError::UnknownResolution(value));
+            };
+            self.resolution = resolution;
+            self.reset_transfer();
+        }
+        Ok(())
+    }
+
+    // Pushes the frame, which is sent from the next transfer.
+    pub fn push_frame(&mut self, frame: IrFrame) {
+        self.frame = Some(frame);
+    }
+
+    // Advances to the next fragment of the acknowledged one.
+    pub fn ack_fragment(&mut self, fragment: u8) {
+        if !self.is_sent || usize::from(fragment) != self.fragment {
+            return;
+        }
+        self.fragment += 1;
+        self.is_sent = false;
+        if self.fragment >= self.resolution.fragment_count() {
+            self.reset_transfer();
+        }
+    }
+
+    // Sends the missed fragment again.
+    pub fn request_fragment(&mut self, fragment: u8) {
+        if usize::from(fragment) < self.resolution.fragment_count() {
+            self.fragment = fragment.into();
+            self.is_sent = false;
+        }
+    }
+
+    // Returns the fragment number and the image data of the current fragment,
+    // which is repeated until it's acknowledged by the host.
+    pub fn current_fragment(&mut self) -> Option<(u8, &[u8])> {
+        if self.mode != IrMode::ImageTransfer {
+            return None;
+        }
+        if self.transfer.is_empty() {
+            self.transfer = match &self.frame {
+                Some(frame) => frame.resample(self.resolution),
+                None => vec![0; self.resolution.width() * self.resolution.height()],
+            };
+        }
+        self.is_sent = true;
+        let start = self.fragment * IR_FRAGMENT_SIZE;
+        Some((
+            self.fragment as u8,
+            &self.transfer[start..start + IR_FRAGMENT_SIZE],
+        ))
+    }
+
+    fn reset_transfer(&mut self) {
+        self.transfer.clear();
+        self.fragment = 0;
+        self.is_sent = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IrCamera, IrFrame, IrMode, IrResolution, IR_FRAGMENT_SIZE};
+
+    #[test]
+    fn resolution_fragments() {
+        assert_eq!(IrResolution::R320x240.fragment_count(), 256);
+        assert_eq!(IrResolution::R160x120.fragment_count(), 64);
+        assert_eq!(IrResolution::R80x60.fragment_count(), 16);
+        assert_eq!(IrResolution::R40x30.fragment_count(), 4);
+        assert!(IrFrame::new(4, 3, vec![0; 11]).is_err());
+    }
+
+    #[test]
+    fn stream_fragments() {
+        let mut camera = IrCamera::new();
+        assert!(camera.current_fragment().is_none());
+        camera.set_mode(IrMode::ImageTransfer);
+        camera.write_registers(&[(0x00, 0x2E, 0x69)]).unwrap();
+        assert!(camera.write_registers(&[(0x00, 0x2E, 0x01)]).is_err());
+        // A 2x2 frame scaled to 40x30: left half is 0x10, right half is 0x20.
+        camera.push_frame(IrFrame::new(2, 2, vec![0x10, 0x20, 0x10, 0x20]).unwrap());
+
+        let (fragment, data) = camera.current_fragment().unwrap();
+        assert_eq!(fragment, 0);
+        assert_eq!(data.len(), IR_FRAGMENT_SIZE);
+        assert_eq!(
+            (data[0], data[19], data[20], data[39]),
+            (0x10, 0x10, 0x20, 0x20)
+        );
+        // Repeated until acknowledged.
+        camera.ack_fragment(1);
+        assert_eq!(camera.current_fragment().unwrap().0, 0);
+        for i in 0..3 {
+            camera.ack_fragment(i);
+            camera.current_fragment().unwrap();
+        }
+        assert_eq!(camera.current_fragment().unwrap().0, 3);
+        camera.request_fragment(1);
+        assert_eq!(camera.current_fragment().unwrap().0, 1);
+        for i in 1..4 {
+            camera.current_fragment().unwrap();
+            camera.ack_fragment(i);
+        }
+        // Starts over with the next frame.
+        camera.push_frame(IrFrame::new(1, 1, vec![0xFF]).unwrap());
+        let (fragment, data) = camera.current_fragment().unwrap();
+        assert_eq!(fragment, 0);
+        assert!(data.iter().all(|&b| b == 0xFF));
+    }
+}
@@ -0,0 +1,189 @@
+// Player lights and home light states set by the host.
+//
+// Ref: https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/bluetooth_hid_subcommands_notes.md#subcommand-0x30-set-player-lights
+
+#[derive(Clone, Copy, Default, Debug, Eq, PartialEq, Hash)]
+pub enum PlayerLight {
+    #[default]
+    Off,
+    On,
+    Flash,
+}
+
+// The pattern of the 4 player lights, ordered from the first LED to the last.
+#[derive(Clone, Copy, Default, Debug, Eq, PartialEq, Hash)]
+pub struct PlayerLights(pub [PlayerLight; 4]);
+
+impl PlayerLights {
+    // Low nibble turns the LEDs on, high nibble makes them flash. On takes
+    // precedence over flash.
+    pub fn from_byte(byte: u8) -> Self {
+        let mut lights = [PlayerLight::Off; 4];
+        for (i, light) in lights.iter_mut().enumerate() {
+            if byte & (1 << i) != 0 {
+                *light = PlayerLight::On;
+            } else if byte & (1 << (i + 4)) != 0 {
+                *light = PlayerLight::Flash;
+            }
+        }
+        Self(lights)
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        self.0
+            .iter()
+            .enumerate()
+            .fold(0, |byte, (i, light)| match light {
+                PlayerLight::Off => byte,
+                PlayerLight::On => byte | (1 << i),
+                PlayerLight::Flash => byte | (1 << (i + 4)),
+            })
+    }
+
+    // The player number assigned by the Switch, which lights up the LEDs
+    // cumulatively (e.g. player 3 => on, on, on, off). Other patterns such as
+    // the ones for player 5 to 8 are not mapped at the moment.
+    pub fn player_number(&self) -> Option<u8> {
+        match self.0 {
+            [PlayerLight::On, PlayerLight::Off, PlayerLight::Off, PlayerLight::Off] => Some(1),
+            [PlayerLight::On, PlayerLight::On, PlayerLight::Off, PlayerLight::Off] => Some(2),
+            [PlayerLight::On, PlayerLight::On, PlayerLight::On, PlayerLight::Off] => Some(3),
+            [PlayerLight::On, PlayerLight::On, PlayerLight::On, PlayerLight::On] => Some(4),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug, Eq, PartialEq, Hash)]
+pub struct HomeLightCycle {
+    // LED intensity in the range of [0x0, 0xF].
+    pub intensity: u8,
+    // Fading transition duration, a multiplier of the base duration.
+    pub fade_duration: u8,
+    // Duration of the LED staying in the intensity, a multiplier of the base duration.
+    pub duration: u8,
+}
+
+// Home light (mini cycle) pattern sent by `SetHomeLight`.
+//
+// Byte     0                           1
+//          cycles[7:4] | base[3:0]     intensity[7:4] | repeat[3:0]
+//
+// Followed by groups of 3 bytes for every 2 mini cycles:
+//          intensity1[7:4] | intensity2[3:0], fade1 | duration1, fade2 | duration2
+#[derive(Clone, Default, Debug, Eq, PartialEq, Hash)]
+pub struct HomeLight {
+    // Base duration of mini cycles, 0x0 means the LED is off.
+    pub base_duration: u8,
+    // LED start intensity in the range of [0x0, 0xF].
+    pub start_intensity: u8,
+    // Number of full cycles, 0x0 means repeating forever.
+    pub repeat_count: u8,
+    pub cycles: Vec<HomeLightCycle>,
+}
+
+impl HomeLight {
+    pub const MAX_CYCLES: usize = 15;
+
+    pub fn with_bytes(bytes: &[u8]) -> Option<Self> {
+        let &[b0, b1] = bytes.get(..2)? else {
+            return None;
+        };
+        let num_cycles = usize::from(b0 >> 4);
+        let mut cycles = Vec::with_capacity(num_cycles);
+        for i in 0..num_cycles {
+            let offset = 2 + (i / 2) * 3;
+            let intensities = *bytes.get(offset)?;
+            let timing = *bytes.get(offset + 1 + i % 2)?;
+            cycles.push(HomeLightCycle {
+                intensity: if i % 2 == 0 {
+                    intensities >> 4
+                } else {
+                    intensities & 0x0F
+                },
+                fade_duration: timing >> 4,
+                duration: timing & 0x0F,
+            });
+        }
+        Some(Self {
+            base_duration: b0 & 0x0F,
+            start_intensity: b1 >> 4,
+            repeat_count: b1 & 0x0F,
+            cycles,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let cycles = &self.cycles[..self.cycles.len().min(Self::MAX_CYCLES)];
+        let mut buf = vec![0u8; 2 + cycles.len().div_ceil(2) * 3];
+        buf[0] = (cycles.len() as u8) << 4 | (self.base_duration & 0x0F);
+        buf[1] = (self.start_intensity & 0x0F) << 4 | (self.repeat_count & 0x0F);
+        for (i, cycle) in cycles.iter().enumerate() {
+            let offset = 2 + (i / 2) * 3;
+            buf[offset] |= if i % 2 == 0 {
+                (cycle.intensity & 0x0F) << 4
+            } else {
+                cycle.intensity & 0x0F
+            };
+            buf[offset + 1 + i % 2] = (cycle.fade_duration & 0x0F) << 4 | (cycle.duration & 0x0F);
+        }
+        buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HomeLight, HomeLightCycle, PlayerLight, PlayerLights};
+
+    #[test]
+    fn player_lights_byte() {
+        let lights = PlayerLights::from_byte(0x03);
+        assert_eq!(lights.player_number(), Some(2));
+        assert_eq!(lights.to_byte(), 0x03);
+
+        let lights = PlayerLights::from_byte(0xF1);
+        assert_eq!(
+            lights.0,
+            [
+                PlayerLight::On,
+                PlayerLight::Flash,
+                PlayerLight::Flash,
+                PlayerLight::Flash
+            ]
+        );
+        assert_eq!(lights.player_number(), None);
+        assert_eq!(lights.to_byte(), 0xE1);
+
+        assert_eq!(PlayerLights::from_byte(0x0F).player_number(), Some(4));
+        assert_eq!(PlayerLights::from_byte(0x00).player_number(), None);
+    }
+
+    #[test]
+    fn home_light_bytes() {
+        let bytes = [0x2F, 0xF1, 0xF0, 0x11, 0x22];
+        let home_light = HomeLight::with_bytes(&bytes).unwrap();
+        assert_eq!(home_light.base_duration, 0xF);
+        assert_eq!(home_light.start_intensity, 0xF);
+        assert_eq!(home_light.repeat_count, 0x1);
+        assert_eq!(
+            home_light.cycles,
+            vec![
+                HomeLightCycle {
+                    intensity: 0xF,
+                    fade_duration: 0x1,
+                    duration: 0x1,
+                },
+                HomeLightCycle {
+                    intensity: 0x0,
+                    fade_duration: 0x2,
+                    duration: 0x2,
+                },
+            ]
+        );
+        assert_eq!(home_light.to_bytes(), bytes);
+
+        // Truncated mini cycle data.
+        assert!(HomeLight::with_bytes(&[0x2F, 0xF1, 0xF0, 0x11]).is_none());
+        assert!(HomeLight::with_bytes(&[0x0F]).is_none());
+    }
+}
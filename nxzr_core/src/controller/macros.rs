@@ -0,0 +1,580 @@
+use super::{
+    sequence::{Hold, InputSequence},
+    state::{button::ButtonKey, stick::StickState, StateError},
+    ControllerType,
+};
+use std::{collections::HashMap, ops::Range, str::FromStr};
+use tokio::time::Duration;
+
+// Default hold of `press` without a duration.
+const DEFAULT_PRESS_HOLD: Hold = Hold::Duration(Duration::from_millis(100));
+// Upper bound of the statements run while compiling, which keeps the nested
+// loops from blowing up.
+const MAX_EXPANDED_STATEMENTS: usize = 100_000;
+
+#[derive(Clone, Debug, Eq, PartialEq, thiserror::Error)]
+pub enum MacroErrorKind {
+    #[error("unknown command `{0}`")]
+    UnknownCommand(String),
+    #[error("missing {0}")]
+    MissingArgument(&'static str),
+    #[error("unexpected argument `{0}`")]
+    UnexpectedArgument(String),
+    #[error("unknown button `{0}`")]
+    UnknownButton(String),
+    #[error("unknown stick `{0}`, expected `l` or `r`")]
+    UnknownStick(String),
+    #[error("invalid stick position `{0}`, expected a number from -1.0 to 1.0")]
+    InvalidStickPosition(String),
+    #[error("invalid duration `{0}`, expected a number followed by `ms`, `s` or `f`")]
+    InvalidDuration(String),
+    #[error("invalid count `{0}`, expected a positive integer")]
+    InvalidCount(String),
+    #[error("label `{0}` is already defined")]
+    DuplicatedLabel(String),
+    #[error("label `{0}` is not defined before the loop")]
+    UnknownLabel(String),
+    #[error("macro runs more than {MAX_EXPANDED_STATEMENTS} statements")]
+    TooLong,
+}
+
+// Error with the position in the macro source, where the line is 1-based and
+// the span is the byte range within the line.
+#[derive(Clone, Debug, Eq, PartialEq, thiserror::Error)]
+#[error("line {line}, column {}: {kind}", span.start + 1)]
+pub struct MacroError {
+    pub line: usize,
+    pub span: Range<usize>,
+    pub kind: MacroErrorKind,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Stick {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    // Presses the buttons for the hold, then releases them for a report.
+    Press {
+        buttons: Vec<ButtonKey>,
+        hold: Hold,
+    },
+    // Keeps the buttons pressed until released.
+    Hold(Vec<ButtonKey>),
+    Release(Vec<ButtonKey>),
+    // Moves the stick to the normalized position, or the center if `None`.
+    Stick {
+        stick: Stick,
+        position: Option<(f32, f32)>,
+    },
+    Wait(Hold),
+    Label(String),
+    // Runs the statements from the label `count` times in total.
+    Loop {
+        label: String,
+        count: u32,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement {
+    pub line: usize,
+    pub span: Range<usize>,
+    pub command: Command,
+}
+
+impl Statement {
+    fn error(&self, kind: MacroErrorKind) -> MacroError {
+        MacroError {
+            line: self.line,
+            span: self.span.clone(),
+            kind,
+        }
+    }
+}
+
+// Line-based macro of the controller inputs, e.g.:
+//
+// ```text
+// # Mash A 10 times, then walk right for a second.
+// label mash
+// press A 3f
+// wait 3f
+// loop mash 10
+// stick l 1.0 0.0
+// wait 1s
+// stick l center
+// hold ZL
+// press A B 100ms
+// release ZL
+// ```
+//
+// Durations are in `ms`, `s` or `f` (frames), where a frame is an input
+// report. Stick positions are normalized from -1.0 to 1.0, and `#` starts a
+// comment.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Macro {
+    statements: Vec<Statement>,
+}
+
+impl Macro {
+    pub fn parse(source: &str) -> Result<Self, MacroError> {
+        let mut statements = vec![];
+        for (index, line) in source.lines().enumerate() {
+            let line = line.split_once('#').map_or(line, |(code, _)| code);
+            let tokens = Tokens::new(index + 1, line);
+            if let Some(statement) = tokens.parse()? {
+                statements.push(statement);
+            }
+        }
+        Ok(Self { statements })
+    }
+
+    pub fn statements(&self) -> &[Statement] {
+        &self.statements
+    }
+
+    // Expands the labels and the loops into the sequence of the controller
+    // state changes.
+    pub fn compile(&self) -> Result<InputSequence, MacroError> {
+        let mut labels = HashMap::new();
+        for (index, statement) in self.statements.iter().enumerate() {
+            let kind = match &statement.command {
+                Command::Label(label) => labels
+                    .insert(label.as_str(), index)
+                    .map(|_| MacroErrorKind::DuplicatedLabel(label.clone())),
+                Command::Loop { label, .. } => (!labels.contains_key(label.as_str()))
+                    .then(|| MacroErrorKind::UnknownLabel(label.clone())),
+                _ => None,
+            };
+            if let Some(kind) = kind {
+                return Err(statement.error(kind));
+            }
+        }
+
+        let mut sequence = InputSequence::new();
+        // Remaining iterations of the loops currently running.
+        let mut remaining_loops: HashMap<usize, u32> = HashMap::new();
+        let mut index = 0;
+        let mut expanded = 0;
+        while let Some(statement) = self.statements.get(index) {
+            expanded += 1;
+            if expanded > MAX_EXPANDED_STATEMENTS {
+                return Err(statement.error(MacroErrorKind::TooLong));
+            }
+            index += 1;
+            sequence = match &statement.command {
+                Command::Press { buttons, hold } => {
+                    let sequence = set_buttons(sequence, buttons.clone(), true, *hold);
+                    set_buttons(sequence, buttons.clone(), false, Hold::Reports(1))
+                }
+                Command::Hold(buttons) => {
+                    set_buttons(sequence, buttons.clone(), true, Hold::Reports(0))
+                }
+                Command::Release(buttons) => {
+                    set_buttons(sequence, buttons.clone(), false, Hold::Reports(0))
+                }
+                Command::Stick { stick, position } => {
+                    let (stick, position) = (*stick, *position);
+                    sequence.then(
+                        move |state| {
+                            let stick_state = match stick {
+                                Stick::Left => state.l_stick_state_mut(),
+                                Stick::Right => state.r_stick_state_mut(),
+                            };
+                            set_stick(stick_state, position)
+                        },
+                        Hold::Reports(0),
+                    )
+                }
+                Command::Wait(hold) => sequence.wait(*hold),
+                Command::Label(_) => sequence,
+                Command::Loop { label, count } => {
+                    let remaining = remaining_loops.entry(index).or_insert(count - 1);
+                    if *remaining > 0 {
+                        *remaining -= 1;
+                        index = labels[label.as_str()];
+                    } else {
+                        // Rewinds for when the outer loop comes back.
+                        remaining_loops.remove(&index);
+                    }
+                    sequence
+                }
+            };
+        }
+        Ok(sequence)
+    }
+}
+
+impl FromStr for Macro {
+    type Err = MacroError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+fn set_buttons(
+    sequence: InputSequence,
+    buttons: Vec<ButtonKey>,
+    flag: bool,
+    hold: Hold,
+) -> InputSequence {
+    sequence.then(
+        move |state| {
+            for button in buttons {
+                state.button_state_mut().set_button(button, flag)?;
+            }
+            Ok(())
+        },
+        hold,
+    )
+}
+
+fn set_stick(stick_state: &mut StickState, position: Option<(f32, f32)>) -> Result<(), StateError> {
+    match position {
+        Some((horizontal, vertical)) => {
+            stick_state.set_horizontal_scale(horizontal)?;
+            stick_state.set_vertical_scale(vertical)
+        }
+        None => stick_state.reset_to_center(),
+    }
+}
+
+// Whitespace separated tokens of a line along with their spans.
+struct Tokens<'a> {
+    line: usize,
+    tokens: Vec<(Range<usize>, &'a str)>,
+    next: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(line: usize, source: &'a str) -> Self {
+        let mut tokens = vec![];
+        let mut start = None;
+        for (index, c) in source.char_indices().chain([(source.len(), ' ')]) {
+            match (start, c.is_whitespace()) {
+                (None, false) => start = Some(index),
+                (Some(begin), true) => {
+                    tokens.push((begin..index, &source[begin..index]));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        Self {
+            line,
+            tokens,
+            next: 0,
+        }
+    }
+
+    fn parse(mut self) -> Result<Option<Statement>, MacroError> {
+        let Some((span, name)) = self.tokens.first().cloned() else {
+            return Ok(None);
+        };
+        let end = self.tokens.last().map_or(span.end, |(span, _)| span.end);
+        self.next = 1;
+        let command = match name.to_ascii_lowercase().as_str() {
+            "press" => {
+                let hold = match self.tokens.last() {
+                    Some((_, token)) if self.tokens.len() > 2 && starts_with_digit(token) => {
+                        let (span, token) = self.tokens.pop().unwrap();
+                        parse_hold(token).ok_or_else(|| {
+                            self.error(span, MacroErrorKind::InvalidDuration(token.into()))
+                        })?
+                    }
+                    _ => DEFAULT_PRESS_HOLD,
+                };
+                Command::Press {
+                    buttons: self.buttons()?,
+                    hold,
+                }
+            }
+            "hold" => Command::Hold(self.buttons()?),
+            "release" => Command::Release(self.buttons()?),
+            "stick" => {
+                let (stick_span, stick) = self.expect("stick")?;
+                let stick = match stick.to_ascii_lowercase().as_str() {
+                    "l" | "left" => Stick::Left,
+                    "r" | "right" => Stick::Right,
+                    _ => {
+                        return Err(
+                            self.error(stick_span, MacroErrorKind::UnknownStick(stick.into()))
+                        )
+                    }
+                };
+                let (span, horizontal) = self.expect("stick position")?;
+                let position = if horizontal.eq_ignore_ascii_case("center") {
+                    None
+                } else {
+                    let horizontal = self.stick_position(span, horizontal)?;
+                    let (span, vertical) = self.expect("vertical stick position")?;
+                    Some((horizontal, self.stick_position(span, vertical)?))
+                };
+                Command::Stick { stick, position }
+            }
+            "wait" => {
+                let (span, token) = self.expect("duration")?;
+                Command::Wait(parse_hold(token).ok_or_else(|| {
+                    self.error(span, MacroErrorKind::InvalidDuration(token.into()))
+                })?)
+            }
+            "label" => Command::Label(self.expect("label")?.1.into()),
+            "loop" => {
+                let label = self.expect("label")?.1.into();
+                let (span, token) = self.expect("count")?;
+                let count = token
+                    .parse()
+                    .ok()
+                    .filter(|count| *count > 0)
+                    .ok_or_else(|| self.error(span, MacroErrorKind::InvalidCount(token.into())))?;
+                Command::Loop { label, count }
+            }
+            _ => return Err(self.error(span, MacroErrorKind::UnknownCommand(name.into()))),
+        };
+        if let Some((span, token)) = self.tokens.get(self.next).cloned() {
+            return Err(self.error(span, MacroErrorKind::UnexpectedArgument(token.into())));
+        }
+        Ok(Some(Statement {
+            line: self.line,
+            span: span.start..end,
+            command,
+        }))
+    }
+
+    fn expect(&mut self, argument: &'static str) -> Result<(Range<usize>, &'a str), MacroError> {
+        match self.tokens.get(self.next).cloned() {
+            Some(token) => {
+                self.next += 1;
+                Ok(token)
+            }
+            None => {
+                let end = self.tokens.last().map_or(0, |(span, _)| span.end);
+                Err(self.error(end..end, MacroErrorKind::MissingArgument(argument)))
+            }
+        }
+    }
+
+    fn buttons(&mut self) -> Result<Vec<ButtonKey>, MacroError> {
+        let mut buttons = vec![self.button()?];
+        while self.next < self.tokens.len() {
+            buttons.push(self.button()?);
+        }
+        Ok(buttons)
+    }
+
+    fn button(&mut self) -> Result<ButtonKey, MacroError> {
+        let (span, token) = self.expect("button")?;
+        // Joy-Cons together have all of the buttons.
+        [ControllerType::JoyConL, ControllerType::JoyConR]
+            .into_iter()
+            .flat_map(ButtonKey::available_buttons)
+            .find(|key| key.to_string().eq_ignore_ascii_case(token))
+            .copied()
+            .ok_or_else(|| self.error(span, MacroErrorKind::UnknownButton(token.into())))
+    }
+
+    fn stick_position(&self, span: Range<usize>, token: &str) -> Result<f32, MacroError> {
+        token
+            .parse::<f32>()
+            .ok()
+            .filter(|position| (-1.0..=1.0).contains(position))
+            .ok_or_else(|| self.error(span, MacroErrorKind::InvalidStickPosition(token.into())))
+    }
+
+    fn error(&self, span: Range<usize>, kind: MacroErrorKind) -> MacroError {
+        MacroError {
+            line: self.line,
+            span,
+            kind,
+        }
+    }
+}
+
+fn starts_with_digit(token: &str) -> bool {
+    token.starts_with(|c: char| c.is_ascii_digit())
+}
+
+// Parses the duration like `100ms`, `1.5s`, or `3f` in frames.
+fn parse_hold(token: &str) -> Option<Hold> {
+    if let Some(frames) = token.strip_suffix('f') {
+        return frames.parse().ok().map(Hold::Reports);
+    }
+    let (value, unit) = match token.strip_suffix("ms") {
+        Some(millis) => (millis, 1000.0),
+        None => (token.strip_suffix('s')?, 1.0),
+    };
+    let value: f64 = value.parse().ok()?;
+    // Rejects the negative, non-finite and overflowing values.
+    Duration::try_from_secs_f64(value / unit)
+        .ok()
+        .map(Hold::Duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, Macro, MacroError, MacroErrorKind, Stick};
+    use crate::controller::{
+        sequence::{Hold, Sequencer},
+        spi_flash::SpiFlash,
+        state::{button::ButtonKey, ControllerState, ControllerStateConfig},
+    };
+    use tokio::time::{Duration, Instant};
+
+    #[test]
+    fn parse_macro() {
+        let source = "\
+            # Comment\n\
+            press a b 3f\n\
+            \n\
+            hold ZL # Comment\n\
+            stick l -1 0.5\n\
+            stick R center\n\
+            wait 1.5s\n\
+            label mash\n\
+            release zl\n\
+            loop mash 2\n";
+        let commands: Vec<_> = Macro::parse(source)
+            .unwrap()
+            .statements()
+            .iter()
+            .map(|statement| statement.command.clone())
+            .collect();
+        assert_eq!(
+            commands,
+            [
+                Command::Press {
+                    buttons: vec![ButtonKey::A, ButtonKey::B],
+                    hold: Hold::Reports(3),
+                },
+                Command::Hold(vec![ButtonKey::Zl]),
+                Command::Stick {
+                    stick: Stick::Left,
+                    position: Some((-1.0, 0.5)),
+                },
+                Command::Stick {
+                    stick: Stick::Right,
+                    position: None,
+                },
+                Command::Wait(Hold::Duration(Duration::from_millis(1500))),
+                Command::Label("mash".into()),
+                Command::Release(vec![ButtonKey::Zl]),
+                Command::Loop {
+                    label: "mash".into(),
+                    count: 2,
+                },
+            ]
+        );
+
+        let error = |source: &str| match source.parse::<Macro>() {
+            Ok(script) => script.compile().unwrap_err(),
+            Err(err) => err,
+        };
+        assert_eq!(
+            error("wait 1s\npress A Q"),
+            MacroError {
+                line: 2,
+                span: 8..9,
+                kind: MacroErrorKind::UnknownButton("Q".into()),
+            }
+        );
+        assert_eq!(
+            error("  jump").to_string(),
+            "line 1, column 3: unknown command `jump`"
+        );
+        assert_eq!(
+            error("wait").kind,
+            MacroErrorKind::MissingArgument("duration")
+        );
+        assert_eq!(error("wait 5").span, 5..6);
+        assert_eq!(
+            error("wait 1e30s"),
+            MacroError {
+                line: 1,
+                span: 5..10,
+                kind: MacroErrorKind::InvalidDuration("1e30s".into()),
+            }
+        );
+        assert_eq!(
+            error("press A 1e30s").kind,
+            MacroErrorKind::InvalidDuration("1e30s".into())
+        );
+        assert_eq!(error("stick l 1.5 0").span, 8..11);
+        assert_eq!(error("label a b").span, 8..9);
+        assert_eq!(error("label a\nloop a 0").span, 7..8);
+        assert_eq!(error("loop a 1\nlabel a").span, 0..8);
+        assert_eq!(error("press A 3f\nloop a 1").line, 2);
+        assert_eq!(
+            Macro::parse("press A 3f").unwrap().statements()[0].span,
+            0..10
+        );
+        assert_eq!(
+            error("label a\nlabel a").kind,
+            MacroErrorKind::DuplicatedLabel("a".into())
+        );
+        assert_eq!(
+            error("label a\nloop a 1000\nloop a 1000").kind,
+            MacroErrorKind::TooLong
+        );
+    }
+
+    #[test]
+    fn compile_macro() {
+        let sequence = Macro::parse(
+            "\
+            label mash\n\
+            label inner\n\
+            press A 2f\n\
+            loop inner 2\n\
+            hold B\n\
+            stick l 1.0 0.0\n\
+            wait 1f\n\
+            release B\n\
+            stick l center\n\
+            loop mash 2\n",
+        )
+        .unwrap()
+        .compile()
+        .unwrap();
+        let mut sequencer = Sequencer::default();
+        let mut done_rx = sequencer.push(sequence);
+        let mut state = ControllerState::with_config(ControllerStateConfig {
+            spi_flash: Some(SpiFlash::default()),
+            ..Default::default()
+        })
+        .unwrap();
+        let center = state.l_stick_state().horizontal();
+        let started_at = Instant::now();
+        let mut reports = vec![];
+        for i in 0..20 {
+            let now = started_at + Duration::from_millis(i * 8);
+            sequencer.advance(now, &mut state);
+            let buttons = state.button_state();
+            reports.push((
+                buttons.is_button_set(ButtonKey::A),
+                buttons.is_button_set(ButtonKey::B),
+                state.l_stick_state().horizontal() > center,
+            ));
+            sequencer.complete(now);
+            if !sequencer.is_active() {
+                break;
+            }
+        }
+        let iteration = [
+            (true, false, false),
+            (true, false, false),
+            (false, false, false),
+            (true, false, false),
+            (true, false, false),
+            (false, false, false),
+            (false, true, true),
+        ];
+        let mut expected = [iteration, iteration].concat();
+        expected.push((false, false, false));
+        assert_eq!(reports, expected);
+        assert!(matches!(done_rx.try_recv(), Ok(Ok(()))));
+    }
+}
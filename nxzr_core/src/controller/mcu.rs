@@ -0,0 +1,486 @@
+use super::{
+    ir_camera::{IrCamera, IrCameraError, IrFrame, IrMode},
+    nfc_tag::NfcTag,
+};
+use std::collections::VecDeque;
+
+// NFC/IR MCU emulation, which serves the NFC tag (e.g. amiibo) and the IR
+// camera images to the host.
+//
+// The host configures the MCU with `SetNfcIrMcuState` and `SetNfcIrMcuConfig`
+// subcommands, then sends requests with 0x11 output reports. The replies are
+// sent back in the MCU data area of 0x31 input reports.
+//
+// Ref: https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/bluetooth_hid_notes.md#output-0x11
+// Ref: https://github.com/CTCaer/jc_toolkit/blob/5.2.0/jctool/jctool.cpp
+
+// Size of the MCU data in 0x31 input reports, the last byte is a CRC-8.
+pub const MCU_DATA_SIZE: usize = 313;
+// Size of the MCU data in `SetNfcIrMcuConfig` replies, the last byte is a CRC-8.
+pub const MCU_CONFIG_REPLY_SIZE: usize = 34;
+
+const MCU_FIRMWARE_VERSION: [u8; 4] = [0x00, 0x08, 0x00, 0x1B];
+
+// Constant data sent with the first packet of the NTAG read reply.
+const NFC_READ_HEADER: [u8; 45] = [
+    0x00, 0x00, 0x00, 0x00, 0x7D, 0xFD, 0xF0, 0x79, 0x36, 0x51, 0xAB, 0xD7, 0x46, 0x6E, 0x39, 0xC1,
+    0x91, 0xBA, 0xBE, 0xB8, 0x56, 0xCE, 0xED, 0xF1, 0xCE, 0x44, 0xCC, 0x75, 0xEA, 0xFB, 0x27, 0x09,
+    0x4D, 0x08, 0x7A, 0xE8, 0x03, 0x00, 0x3B, 0x3C, 0x77, 0x78, 0x86, 0x00, 0x00,
+];
+// Size of the tag data sent with the first packet of the NTAG read reply.
+const NFC_READ_FIRST_CHUNK_SIZE: usize = 245;
+
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum McuError {
+    // Returned when a request is received while the MCU is suspended.
+    #[error("the MCU is suspended")]
+    Suspended,
+    #[error("a length of the request is too short")]
+    TooShort,
+    #[error("unknown MCU command: {0:#X}")]
+    UnknownCommand(u8),
+    #[error("unknown MCU mode: {0:#X}")]
+    UnknownMode(u8),
+    #[error("unknown NFC command: {0:#X}")]
+    UnknownNfcCommand(u8),
+    #[error("unknown IR command: {0:#X}")]
+    UnknownIrCommand(u8),
+    #[error("ir camera: {0}")]
+    IrCamera(IrCameraError),
+    // Returned when the request is not available in the current mode.
+    #[error("invalid MCU mode for the request: {0:?}")]
+    InvalidMode(McuMode),
+    #[error("no NFC tag is available on the reader")]
+    NoTagAvailable,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum McuMode {
+    #[default]
+    Suspended,
+    Standby,
+    Nfc,
+    Ir,
+}
+
+impl McuMode {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0x01 => Some(Self::Standby),
+            0x04 => Some(Self::Nfc),
+            0x05 => Some(Self::Ir),
+            _ => None,
+        }
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        match self {
+            Self::Suspended => 0x00,
+            Self::Standby => 0x01,
+            Self::Nfc => 0x04,
+            Self::Ir => 0x05,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub enum NfcState {
+    #[default]
+    None,
+    Polling,
+    PollAgain,
+}
+
+impl NfcState {
+    pub fn to_byte(&self) -> u8 {
+        match self {
+            Self::None => 0x00,
+            Self::Polling => 0x01,
+            Self::PollAgain => 0x09,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Mcu {
+    mode: McuMode,
+    nfc_state: NfcState,
+    nfc_tag: Option<NfcTag>,
+    ir_camera: IrCamera,
+    responses: VecDeque<[u8; MCU_DATA_SIZE]>,
+}
+
+impl Mcu {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn mode(&self) -> McuMode {
+        self.mode
+    }
+
+    pub fn nfc_state(&self) -> NfcState {
+        self.nfc_state
+    }
+
+    pub fn nfc_tag(&self) -> Option<&NfcTag> {
+        self.nfc_tag.as_ref()
+    }
+
+    // Puts the tag on the reader, or takes it off if `None`.
+    pub fn set_nfc_tag(&mut self, nfc_tag: Option<NfcTag>) {
+        self.nfc_tag = nfc_tag;
+        if self.nfc_state != NfcState::None {
+            self.nfc_state = NfcState::Polling;
+        }
+    }
+
+    pub fn ir_camera(&self) -> &IrCamera {
+        &self.ir_camera
+    }
+
+    // Pushes the frame to be sent as the IR camera image.
+    pub fn push_ir_frame(&mut self, frame: IrFrame) {
+        self.ir_camera.push_frame(frame);
+    }
+
+    pub fn suspend(&mut self) {
+        self.mode = McuMode::Suspended;
+        self.nfc_state = NfcState::None;
+        self.responses.clear();
+    }
+
+    pub fn resume(&mut self) {
+        if self.mode == McuMode::Suspended {
+            self.mode = McuMode::Standby;
+        }
+    }
+
+    // Handles the arguments of `SetNfcIrMcuConfig` subcommand, then returns the
+    // reply data of the subcommand.
+    pub fn configure(&mut self, args: &[u8]) -> Result<[u8; MCU_CONFIG_REPLY_SIZE], McuError> {
+        let &[command, sub_command, arg, ..] = args else {
+            return Err(McuError::TooShort);
+        };
+        match command {
+            // Set MCU mode
+            0x21 => {
+                let Some(mode) = McuMode::from_byte(arg) else {
+                    return Err(McuError::UnknownMode(arg));
+                };
+                self.mode = mode;
+                self.nfc_state = NfcState::None;
+                self.ir_camera.set_mode(IrMode::Ready);
+                self.responses.clear();
+                Ok(self.config_reply())
+            }
+            // Configure IR camera
+            0x23 => {
+                if self.mode != McuMode::Ir {
+                    return Err(McuError::InvalidMode(self.mode));
+                }
+                match sub_command {
+                    // Set IR mode, followed by the number of fragments which is
+                    // derived from the resolution instead.
+                    0x01 => self.ir_camera.set_mode(IrMode::from_byte(arg)),
+                    // Write registers, followed by (page, register, value) tuples.
+                    0x04 => {
+                        let Some(data) = args.get(3..3 + usize::from(arg) * 3) else {
+                            return Err(McuError::TooShort);
+                        };
+                        let registers: Vec<(u8, u8, u8)> = data
+                            .chunks_exact(3)
+                            .map(|register| (register[0], register[1], register[2]))
+                            .collect();
+                        self.ir_camera
+                            .write_registers(&registers)
+                            .map_err(McuError::IrCamera)?;
+                    }
+                    sub_command => return Err(McuError::UnknownIrCommand(sub_command)),
+                }
+                let mut buf = [0u8; MCU_CONFIG_REPLY_SIZE];
+                buf[0] = 0x0B;
+                buf[MCU_CONFIG_REPLY_SIZE - 1] = crc8(&buf[..MCU_CONFIG_REPLY_SIZE - 1]);
+                Ok(buf)
+            }
+            command => Err(McuError::UnknownCommand(command)),
+        }
+    }
+
+    // Reply data of `SetNfcIrMcuConfig` subcommand, which reports the MCU status.
+    pub fn config_reply(&self) -> [u8; MCU_CONFIG_REPLY_SIZE] {
+        let mut buf = [0u8; MCU_CONFIG_REPLY_SIZE];
+        buf[0..3].copy_from_slice(&[0x01, 0x00, 0xFF]);
+        buf[3..7].copy_from_slice(&MCU_FIRMWARE_VERSION);
+        buf[7] = self.mode.to_byte();
+        buf[MCU_CONFIG_REPLY_SIZE - 1] = crc8(&buf[..MCU_CONFIG_REPLY_SIZE - 1]);
+        buf
+    }
+
+    // Handles the request of 0x11 output reports, starting from the MCU
+    // command byte. Replies are queued to be sent with the next input reports.
+    pub fn process_request(&mut self, request: &[u8]) -> Result<(), McuError> {
+        if self.mode == McuMode::Suspended {
+            return Err(McuError::Suspended);
+        }
+        let Some(&command) = request.first() else {
+            return Err(McuError::TooShort);
+        };
+        match command {
+            // Status request
+            0x01 => {
+                let mut buf = [0u8; MCU_DATA_SIZE];
+                buf[0..3].copy_from_slice(&[0x01, 0x00, 0x00]);
+                buf[3..7].copy_from_slice(&MCU_FIRMWARE_VERSION);
+                buf[7] = self.mode.to_byte();
+                self.queue_response(buf);
+            }
+            // NFC command
+            0x02 => {
+                let Some(&nfc_command) = request.get(1) else {
+                    return Err(McuError::TooShort);
+                };
+                self.process_nfc_command(nfc_command)?;
+            }
+            // IR command, which acknowledges the received fragment or requests
+            // the missed one.
+            0x03 => {
+                if self.mode != McuMode::Ir {
+                    return Err(McuError::InvalidMode(self.mode));
+                }
+                let Some(&[_, is_missed, missed_fragment, ack_fragment]) = request.get(1..5) else {
+                    return Err(McuError::TooShort);
+                };
+                if is_missed == 0x01 {
+                    self.ir_camera.request_fragment(missed_fragment);
+                } else {
+                    self.ir_camera.ack_fragment(ack_fragment);
+                }
+            }
+            command => return Err(McuError::UnknownCommand(command)),
+        }
+        Ok(())
+    }
+
+    // Returns the MCU data to be sent with the next 0x31 input report.
+    pub fn next_data(&mut self) -> [u8; MCU_DATA_SIZE] {
+        if let Some(buf) = self.responses.pop_front() {
+            return buf;
+        }
+        match self.mode {
+            // Keeps reporting the reader status, so the host notices the tag.
+            McuMode::Nfc => with_crc(self.nfc_status()),
+            McuMode::Ir => match self.ir_camera.current_fragment() {
+                Some((fragment, data)) => {
+                    let mut buf = [0u8; MCU_DATA_SIZE];
+                    buf[0..4].copy_from_slice(&[0x03, 0x00, 0x00, fragment]);
+                    buf[10..10 + data.len()].copy_from_slice(data);
+                    with_crc(buf)
+                }
+                None => idle_data(),
+            },
+            _ => idle_data(),
+        }
+    }
+
+    fn process_nfc_command(&mut self, nfc_command: u8) -> Result<(), McuError> {
+        match nfc_command {
+            // Cancel
+            0x00 => self.nfc_state = NfcState::None,
+            // Start polling
+            0x01 => self.nfc_state = NfcState::Polling,
+            // Stop polling
+            0x02 => self.nfc_state = NfcState::None,
+            // Get status
+            0x04 => {}
+            // Read NTAG
+            0x06 => {
+                let Some(nfc_tag) = self.nfc_tag.clone() else {
+                    return Err(McuError::NoTagAvailable);
+                };
+                self.queue_nfc_read(&nfc_tag);
+                // The host polls again after reading the tag.
+                self.nfc_state = NfcState::PollAgain;
+                return Ok(());
+            }
+            nfc_command => return Err(McuError::UnknownNfcCommand(nfc_command)),
+        }
+        let buf = self.nfc_status();
+        self.queue_response(buf);
+        Ok(())
+    }
+
+    fn nfc_status(&self) -> [u8; MCU_DATA_SIZE] {
+        let mut buf = [0u8; MCU_DATA_SIZE];
+        buf[0..7].copy_from_slice(&[0x2A, 0x00, 0x05, 0x00, 0x00, 0x09, 0x31]);
+        buf[7] = self.nfc_state.to_byte();
+        if let Some(nfc_tag) = &self.nfc_tag {
+            if self.nfc_state != NfcState::None {
+                // Tag type (NTAG) and UID length follow.
+                buf[8..16].copy_from_slice(&[0x00, 0x00, 0x00, 0x01, 0x01, 0x02, 0x00, 0x07]);
+                buf[16..23].copy_from_slice(&nfc_tag.uid());
+            }
+        }
+        buf
+    }
+
+    // The tag data is split into 2 packets.
+    fn queue_nfc_read(&mut self, nfc_tag: &NfcTag) {
+        let data = nfc_tag.as_bytes();
+        let mut buf = [0u8; MCU_DATA_SIZE];
+        buf[0..15].copy_from_slice(&[
+            0x3A, 0x00, 0x07, 0x01, 0x00, 0x01, 0x31, 0x02, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00,
+            0x07,
+        ]);
+        buf[15..22].copy_from_slice(&nfc_tag.uid());
+        buf[22..67].copy_from_slice(&NFC_READ_HEADER);
+        buf[67..67 + NFC_READ_FIRST_CHUNK_SIZE].copy_from_slice(&data[..NFC_READ_FIRST_CHUNK_SIZE]);
+        self.queue_response(buf);
+
+        let rest = &data[NFC_READ_FIRST_CHUNK_SIZE..];
+        let mut buf = [0u8; MCU_DATA_SIZE];
+        buf[0..7].copy_from_slice(&[0x3A, 0x00, 0x07, 0x02, 0x00, 0x09, 0x27]);
+        buf[7..7 + rest.len()].copy_from_slice(rest);
+        self.queue_response(buf);
+    }
+
+    fn queue_response(&mut self, buf: [u8; MCU_DATA_SIZE]) {
+        self.responses.push_back(with_crc(buf));
+    }
+}
+
+fn idle_data() -> [u8; MCU_DATA_SIZE] {
+    // 0xFF indicates that there is no data.
+    let mut buf = [0u8; MCU_DATA_SIZE];
+    buf[0] = 0xFF;
+    with_crc(buf)
+}
+
+fn with_crc(mut buf: [u8; MCU_DATA_SIZE]) -> [u8; MCU_DATA_SIZE] {
+    buf[MCU_DATA_SIZE - 1] = crc8(&buf[..MCU_DATA_SIZE - 1]);
+    buf
+}
+
+// CRC-8 with the polynomial of 0x07, used by the MCU data.
+pub fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc8, Mcu, McuMode, NfcState, MCU_DATA_SIZE};
+    use crate::controller::{
+        ir_camera::IrFrame,
+        nfc_tag::{NfcTag, NTAG215_SIZE},
+    };
+
+    fn amiibo() -> NfcTag {
+        let data: Vec<u8> = (0..NTAG215_SIZE).map(|i| i as u8).collect();
+        NfcTag::with_bytes(&data).unwrap()
+    }
+
+    fn assert_crc(buf: &[u8; MCU_DATA_SIZE]) {
+        assert_eq!(buf[MCU_DATA_SIZE - 1], crc8(&buf[..MCU_DATA_SIZE - 1]));
+    }
+
+    #[test]
+    fn configure_mode() {
+        let mut mcu = Mcu::new();
+        mcu.resume();
+        assert_eq!(
+            mcu.configure(&[0x21, 0x00, 0x01]).unwrap(),
+            [
+                0x01, 0x00, 0xFF, 0x00, 0x08, 0x00, 0x1B, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0xC8,
+            ]
+        );
+        assert_eq!(mcu.configure(&[0x21, 0x00, 0x04]).unwrap()[7], 0x04);
+        assert_eq!(mcu.mode(), McuMode::Nfc);
+        assert!(mcu.configure(&[0x21, 0x00, 0x0A]).is_err());
+    }
+
+    #[test]
+    fn request_while_suspended() {
+        let mut mcu = Mcu::new();
+        assert!(mcu.process_request(&[0x01]).is_err());
+        assert_eq!(mcu.next_data()[0], 0xFF);
+        mcu.resume();
+        mcu.process_request(&[0x01]).unwrap();
+        let buf = mcu.next_data();
+        assert_eq!(buf[..8], [0x01, 0x00, 0x00, 0x00, 0x08, 0x00, 0x1B, 0x01]);
+        assert_crc(&buf);
+    }
+
+    #[test]
+    fn poll_and_read_tag() {
+        let mut mcu = Mcu::new();
+        mcu.resume();
+        mcu.configure(&[0x21, 0x00, 0x04]).unwrap();
+        mcu.process_request(&[0x02, 0x01]).unwrap();
+        let buf = mcu.next_data();
+        assert_eq!(buf[..8], [0x2A, 0x00, 0x05, 0x00, 0x00, 0x09, 0x31, 0x01]);
+        assert_eq!(buf[15], 0x00);
+        assert!(mcu.process_request(&[0x02, 0x06]).is_err());
+
+        let tag = amiibo();
+        mcu.set_nfc_tag(Some(tag.clone()));
+        let buf = mcu.next_data();
+        assert_eq!(buf[15], 0x07);
+        assert_eq!(buf[16..23], tag.uid());
+        assert_crc(&buf);
+
+        mcu.process_request(&[0x02, 0x06]).unwrap();
+        let first = mcu.next_data();
+        let second = mcu.next_data();
+        assert_eq!(first[0], 0x3A);
+        assert_eq!(first[15..22], tag.uid());
+        assert_eq!(first[67..312], tag.as_bytes()[..245]);
+        assert_eq!(second[..4], [0x3A, 0x00, 0x07, 0x02]);
+        assert_eq!(second[7..302], tag.as_bytes()[245..]);
+        assert_crc(&first);
+        assert_crc(&second);
+        assert_eq!(mcu.nfc_state(), NfcState::PollAgain);
+
+        mcu.set_nfc_tag(None);
+        let buf = mcu.next_data();
+        assert_eq!(buf[7], 0x01);
+        assert_eq!(buf[15], 0x00);
+    }
+
+    #[test]
+    fn stream_ir_fragments() {
+        let mut mcu = Mcu::new();
+        mcu.resume();
+        assert!(mcu.configure(&[0x23, 0x01, 0x07]).is_err());
+        mcu.configure(&[0x21, 0x00, 0x05]).unwrap();
+        // 80x60 with the image transfer mode.
+        let reply = mcu
+            .configure(&[0x23, 0x04, 0x01, 0x00, 0x2E, 0x64])
+            .unwrap();
+        assert_eq!(reply[0], 0x0B);
+        assert_eq!(mcu.next_data()[0], 0xFF);
+        mcu.configure(&[0x23, 0x01, 0x07, 0x0F]).unwrap();
+        mcu.push_ir_frame(IrFrame::new(1, 1, vec![0x80]).unwrap());
+
+        let buf = mcu.next_data();
+        assert_eq!(buf[..4], [0x03, 0x00, 0x00, 0x00]);
+        assert!(buf[10..310].iter().all(|&b| b == 0x80));
+        assert_crc(&buf);
+        // Acknowledges the fragment 0, then requests the missed one.
+        mcu.process_request(&[0x03, 0x00, 0x00, 0x00, 0x00])
+            .unwrap();
+        assert_eq!(mcu.next_data()[3], 0x01);
+        mcu.process_request(&[0x03, 0x00, 0x01, 0x00, 0x00])
+            .unwrap();
+        assert_eq!(mcu.next_data()[3], 0x00);
+    }
+}
@@ -1,9 +1,19 @@
 use state::button::ButtonKey;
 use strum::{Display, EnumString};
 
+pub mod color;
+pub mod handler;
+pub mod imu_registers;
 pub mod interval;
+pub mod ir_camera;
+pub mod light;
+pub mod macros;
+pub mod mcu;
+pub mod nfc_tag;
 pub mod protocol;
 pub mod report;
+pub mod rumble;
+pub mod sequence;
 pub mod spi_flash;
 pub mod state;
 
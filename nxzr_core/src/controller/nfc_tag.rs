@@ -0,0 +1,86 @@
+use std::{fmt, path::Path};
+
+// Size of the NTAG215 memory (135 pages of 4 bytes), which is the size of the
+// amiibo dumps.
+pub const NTAG215_SIZE: usize = 540;
+
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum NfcTagError {
+    // Returned when the supplied image does not match with the tag size.
+    #[error("invalid image size: {0} bytes, expected {NTAG215_SIZE} bytes")]
+    InvalidImageSize(usize),
+    #[error("io error: {message}")]
+    Io {
+        kind: std::io::ErrorKind,
+        message: String,
+    },
+}
+
+impl From<std::io::Error> for NfcTagError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io {
+            kind: err.kind(),
+            message: err.to_string(),
+        }
+    }
+}
+
+// NTAG215 tag, e.g. an amiibo, served to the host through the NFC/IR MCU.
+#[derive(Clone, PartialEq, Eq)]
+pub struct NfcTag {
+    buf: Box<[u8; NTAG215_SIZE]>,
+}
+
+// Prints the UID only, dumping the entire memory is not really helpful.
+impl fmt::Debug for NfcTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NfcTag")
+            .field("uid", &self.uid())
+            .finish_non_exhaustive()
+    }
+}
+
+impl NfcTag {
+    pub fn with_bytes(data: &[u8]) -> Result<Self, NfcTagError> {
+        let buf: [u8; NTAG215_SIZE] = data
+            .try_into()
+            .map_err(|_| NfcTagError::InvalidImageSize(data.len()))?;
+        Ok(Self { buf: Box::new(buf) })
+    }
+
+    // Loads a raw dump (e.g. an amiibo `.bin` file) from the file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, NfcTagError> {
+        let data = std::fs::read(path)?;
+        Self::with_bytes(&data)
+    }
+
+    // 7 bytes UID, stored in the first two pages with a check byte in between.
+    //
+    // Page     0                       1
+    //          UID0 UID1 UID2 BCC0     UID3 UID4 UID5 UID6
+    pub fn uid(&self) -> [u8; 7] {
+        let mut uid = [0u8; 7];
+        uid[..3].copy_from_slice(&self.buf[0..3]);
+        uid[3..].copy_from_slice(&self.buf[4..8]);
+        uid
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NfcTag, NTAG215_SIZE};
+
+    #[test]
+    fn parse_tag() {
+        let mut data = vec![0u8; NTAG215_SIZE];
+        data[..9].copy_from_slice(&[0x04, 0x11, 0x22, 0xBF, 0x33, 0x44, 0x55, 0x66, 0xAA]);
+        let tag = NfcTag::with_bytes(&data).unwrap();
+        assert_eq!(tag.uid(), [0x04, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        assert_eq!(tag.as_bytes(), &data[..]);
+        assert!(NfcTag::with_bytes(&data[..532]).is_err());
+    }
+}
@@ -1,22 +1,35 @@
 use super::{
-    interval::SendInterval,
+    color::ControllerColors,
+    handler::{SubcommandHandler, SubcommandReply, UnsupportedSubcommandPolicy},
+    imu_registers::ImuRegisters,
+    interval::{SendInterval, SendIntervalConfig},
+    ir_camera::IrFrame,
+    light::{HomeLight, PlayerLights},
+    mcu::{Mcu, McuError, McuMode},
+    nfc_tag::NfcTag,
     report::{
         input::{InputReport, InputReportId, TriggerButtonsElapsedTimeCommand},
         output::{OutputReport, OutputReportId},
-        subcommand::Subcommand,
+        subcommand::{Subcommand, SubcommandId, SubcommandRequest},
         ReportError,
     },
-    spi_flash::SpiFlash,
-    state::{ControllerState, StateError},
+    rumble::Rumble,
+    sequence::{InputSequence, Sequencer},
+    spi_flash::{SpiFlash, SpiFlashError, SECTOR_SIZE},
+    state::{stick::StickCalibration, ControllerState, StateError},
     ControllerType,
 };
+use crate::metrics::{DurationHistogram, RateCounter};
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
 use nxzr_shared::{
     addr::Address,
     event::{setup_event, EventError, SubscriptionReq},
 };
-use std::{future::Future, sync::Mutex};
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
 use strum::{Display, IntoStaticStr};
 use tokio::{
     sync::{mpsc, oneshot, watch, Notify},
@@ -35,12 +48,14 @@ pub enum ControllerProtocolError {
     InputReportCreationFailed,
     #[error("unknown report mode is used for generating input report")]
     UnknownInputReportMode,
-    #[error("write operation is slower than usual: {0:?}, ignoring")]
-    LaggedWrites(Duration),
+    #[error("full report interval must be greater than zero")]
+    InvalidFullReportInterval,
     #[error("write operation is triggered while paused, ignoring")]
     WriteWhilePaused,
     #[error("a report mode has been set, which is identical to previous one")]
     DuplicatedReportModeSet,
+    #[error("input sequence has been dropped before sent")]
+    SequenceAborted,
     #[error("transport error: {message}")]
     Transport {
         kind: std::io::ErrorKind,
@@ -60,6 +75,10 @@ pub enum ControllerProtocolInternalError {
     Report(ReportError),
     #[error("state: {0}")]
     State(StateError),
+    #[error("spi flash: {0}")]
+    SpiFlash(SpiFlashError),
+    #[error("mcu: {0}")]
+    Mcu(McuError),
     #[error("event: {0}")]
     Event(EventError),
 }
@@ -76,6 +95,18 @@ impl From<StateError> for ControllerProtocolError {
     }
 }
 
+impl From<SpiFlashError> for ControllerProtocolError {
+    fn from(err: SpiFlashError) -> Self {
+        Self::Internal(ControllerProtocolInternalError::SpiFlash(err))
+    }
+}
+
+impl From<McuError> for ControllerProtocolError {
+    fn from(err: McuError) -> Self {
+        Self::Internal(ControllerProtocolInternalError::Mcu(err))
+    }
+}
+
 impl From<EventError> for ControllerProtocolError {
     fn from(err: EventError) -> Self {
         Self::Internal(ControllerProtocolInternalError::Event(err))
@@ -103,6 +134,13 @@ struct State {
     pub send_interval: f64,
     pub report_mode: Option<u8>,
     pub connected_at: Option<time::Instant>,
+    pub imu_enabled: bool,
+    pub vibration_enabled: bool,
+    pub rumble_data: [u8; 8],
+    pub player_lights: PlayerLights,
+    pub home_light: Option<HomeLight>,
+    pub imu_registers: ImuRegisters,
+    pub mcu: Mcu,
     pub controller_state: ControllerState,
     // Internally we allow `spi_flash` to be `None`.
     // For public api, however, we don't expose these things at the moment.
@@ -119,12 +157,21 @@ impl Shared {
             state: Mutex::new(State {
                 is_pairing: !reconnect,
                 send_interval: if reconnect {
-                    SendInterval::new(None).to_byte().unwrap()
+                    SendInterval::new(None, controller_state.controller())
+                        .to_byte()
+                        .unwrap()
                 } else {
                     SendInterval::default_byte()
                 },
                 report_mode: None,
                 connected_at: None,
+                imu_enabled: false,
+                vibration_enabled: false,
+                rumble_data: [0x00, 0x01, 0x40, 0x40, 0x00, 0x01, 0x40, 0x40],
+                player_lights: PlayerLights::default(),
+                home_light: None,
+                imu_registers: ImuRegisters::new(),
+                mcu: Mcu::new(),
                 controller_state,
                 spi_flash,
             }),
@@ -161,39 +208,139 @@ pub struct ControllerProtocolConfig {
     pub controller_type: ControllerType,
     pub dev_address: Address,
     pub reconnect: bool,
+    // SPI flash image to serve to the host, uses the default image if `None`.
+    pub spi_flash: Option<SpiFlash>,
+    // Colors of the controller, overrides the ones in the SPI flash if supplied.
+    pub colors: Option<ControllerColors>,
+    // Pacing of the input reports.
+    pub send_interval: SendIntervalConfig,
+    // Custom handler to override or extend the subcommand replies.
+    pub subcommand_handler: Option<Arc<dyn SubcommandHandler>>,
+    // How to reply to the subcommands which are not supported.
+    pub unsupported_subcommand: UnsupportedSubcommandPolicy,
+}
+
+// Public view over the state of the protocol, see `ControllerProtocol::snapshot`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProtocolSnapshot {
+    pub is_pairing: bool,
+    // Interval between the input reports in seconds, `f64::INFINITY` while
+    // the writer waits to be woken up.
+    pub send_interval: f64,
+    pub report_mode: Option<u8>,
+    pub connected_at: Option<time::Instant>,
+}
+
+impl ProtocolSnapshot {
+    fn with_state(state: &State) -> Self {
+        Self {
+            is_pairing: state.is_pairing,
+            send_interval: state.send_interval,
+            report_mode: state.report_mode,
+            connected_at: state.connected_at,
+        }
+    }
+}
+
+// Performance metrics of the protocol, see `ControllerProtocol::metrics`.
+#[derive(Clone, Debug, Default)]
+pub struct ProtocolMetrics {
+    pub reports_read: RateCounter,
+    pub reports_written: RateCounter,
+    // Time taken by the transport to write a report, including the
+    // subcommand replies.
+    pub write_latency: DurationHistogram,
+    // Deviation of the intervals between the paced reports from the send
+    // interval.
+    pub pacing_jitter: DurationHistogram,
+    pub lagged_writes: LaggedWriteStats,
+    // Total time the writer has waited for the protocol to be unpaused.
+    pub paused_duration: Duration,
+    last_paced_at: Option<time::Instant>,
+}
+
+impl ProtocolMetrics {
+    fn record_paced_write(
+        &mut self,
+        now: time::Instant,
+        paused: Option<Duration>,
+        send_interval: f64,
+    ) {
+        let last_paced_at = self.last_paced_at.replace(now);
+        if let Some(paused) = paused {
+            self.paused_duration += paused;
+            return;
+        }
+        // The intervals of the on-demand writes, and of the writes woken up
+        // before the interval, are not the matter of the pacing.
+        let (Some(last_paced_at), Ok(send_interval)) =
+            (last_paced_at, Duration::try_from_secs_f64(send_interval))
+        else {
+            return;
+        };
+        let interval = now - last_paced_at;
+        self.pacing_jitter.record(interval.abs_diff(send_interval));
+    }
 }
 
 #[derive(Debug)]
 pub struct ControllerProtocol {
     state: Shared,
+    // Raw color info of the profile, which is overridden by the configured
+    // colors only in memory.
+    profile_raw_colors: Option<[u8; 13]>,
     controller_type: ControllerType,
+    send_interval_config: SendIntervalConfig,
+    subcommand_handler: Option<Arc<dyn SubcommandHandler>>,
+    unsupported_subcommand: UnsupportedSubcommandPolicy,
     dev_addr: Address,
     notify_data_received: Notify,
     notify_writer_wake: Notify,
     writer_ready_tx: watch::Sender<bool>,
     paused_tx: watch::Sender<bool>,
+    snapshot_tx: watch::Sender<ProtocolSnapshot>,
+    metrics: Mutex<ProtocolMetrics>,
+    sequencer: Mutex<Sequencer>,
     event_sub_tx: mpsc::Sender<SubscriptionReq<Event>>,
     msg_tx: mpsc::Sender<Event>,
 }
 
 impl ControllerProtocol {
     pub fn new(config: ControllerProtocolConfig) -> Result<Self, ControllerProtocolError> {
+        if !config.send_interval.is_valid() {
+            return Err(ControllerProtocolError::InvalidFullReportInterval);
+        }
         let (msg_tx, msg_rx) = mpsc::channel(256);
         let (event_sub_tx, event_sub_rx) = mpsc::channel(1);
         Event::handle_events(msg_rx, event_sub_rx)?;
-        let spi_flash = SpiFlash::new();
+        let mut spi_flash = config.spi_flash.unwrap_or_default();
+        let profile_raw_colors = config.colors.as_ref().map(|colors| {
+            let raw_colors = spi_flash.raw_colors();
+            spi_flash.set_colors(colors);
+            raw_colors
+        });
         let controller_state = ControllerState::with_config(super::state::ControllerStateConfig {
             controller: config.controller_type,
             spi_flash: Some(spi_flash.clone()),
+            ..Default::default()
         })?;
+        let state = Shared::new(controller_state, Some(spi_flash), config.reconnect);
+        let snapshot = state.modify(|state| ProtocolSnapshot::with_state(state));
         Ok(Self {
-            state: Shared::new(controller_state, Some(spi_flash), config.reconnect),
+            state,
+            profile_raw_colors,
             controller_type: config.controller_type,
+            send_interval_config: config.send_interval,
+            subcommand_handler: config.subcommand_handler,
+            unsupported_subcommand: config.unsupported_subcommand,
             dev_addr: config.dev_address,
             notify_data_received: Notify::new(),
             notify_writer_wake: Notify::new(),
             writer_ready_tx: watch::channel(false).0,
             paused_tx: watch::channel(false).0,
+            snapshot_tx: watch::channel(snapshot).0,
+            metrics: Mutex::new(ProtocolMetrics::default()),
+            sequencer: Mutex::new(Sequencer::default()),
             event_sub_tx,
             msg_tx,
         })
@@ -202,6 +349,55 @@ impl ControllerProtocol {
     // Mark a certain point when the connection is established.
     pub fn establish_connection(&self) {
         self.state.set_connected_at(Some(time::Instant::now()));
+        self.update_snapshot();
+    }
+
+    // Returns the current state of the protocol.
+    pub fn snapshot(&self) -> ProtocolSnapshot {
+        self.state
+            .modify(|state| ProtocolSnapshot::with_state(state))
+    }
+
+    // Watches the state of the protocol, which changes when the connection
+    // is established, the pairing ends, or the report mode or the send
+    // interval changes.
+    pub fn watch_snapshot(&self) -> watch::Receiver<ProtocolSnapshot> {
+        self.snapshot_tx.subscribe()
+    }
+
+    // Publishes the changes of the state, along with the events for them.
+    fn update_snapshot(&self) {
+        let snapshot = self.snapshot();
+        let mut logs = vec![];
+        self.snapshot_tx.send_if_modified(|current| {
+            if *current == snapshot {
+                return false;
+            }
+            if let Some(mode) = snapshot.report_mode {
+                if current.report_mode != Some(mode) {
+                    logs.push(LogType::ReportModeChanged(mode));
+                }
+            }
+            if current.send_interval != snapshot.send_interval {
+                logs.push(LogType::SendIntervalChanged(
+                    Duration::try_from_secs_f64(snapshot.send_interval).ok(),
+                ));
+            }
+            *current = snapshot;
+            true
+        });
+        for log in logs {
+            self.emit_event(Event::Log(log));
+        }
+    }
+
+    // Returns a copy of the performance metrics collected so far.
+    pub fn metrics(&self) -> ProtocolMetrics {
+        self.metrics.lock().unwrap().clone()
+    }
+
+    pub fn controller_state(&self) -> ControllerState {
+        self.state.modify(|state| state.controller_state.clone())
     }
 
     // Update the controller state by replacing the current one.
@@ -216,6 +412,20 @@ impl ControllerProtocol {
         self.state.modify_controller_state(f)
     }
 
+    // Queues the sequence of the controller state changes, which is applied
+    // to the input reports sent by the writer. Resolved once the last change
+    // has been sent for its hold.
+    pub async fn send_sequence(
+        &self,
+        sequence: InputSequence,
+    ) -> Result<(), ControllerProtocolError> {
+        let done_rx = self.sequencer.lock().unwrap().push(sequence);
+        done_rx
+            .await
+            .map_err(|_| ControllerProtocolError::SequenceAborted)?
+            .map_err(Into::into)
+    }
+
     // Resolved when the first response is received by the reader.
     pub async fn wait_for_connection(&self) {
         self.notify_data_received.notified().await;
@@ -244,6 +454,11 @@ impl ControllerProtocol {
                 kind: err.kind(),
                 message: err.to_string(),
             })?;
+        self.metrics
+            .lock()
+            .unwrap()
+            .reports_read
+            .record(time::Instant::now());
         let output_report = match OutputReport::with_raw(buf) {
             Ok(output_report) => output_report,
             Err(_) => {
@@ -258,20 +473,23 @@ impl ControllerProtocol {
             self.emit_event(Event::Warning(ControllerProtocolError::OutputReportIdParseFailed));
             return Ok(());
         };
-        match output_report_id {
-            OutputReportId::SubCommand => {
-                self.reply_to_subcommand(transport, &output_report).await?;
-            }
-            OutputReportId::RumbleOnly => {
-                // Rumble: noop
-            }
-            OutputReportId::RequestIrNfcMcu => {
-                self.emit_event(Event::Warning(
-                    ControllerProtocolError::NotImplemented("attempting to request subcommand: RequestIrNfcMcu, which is not implemented, ignoring.".into()
-                )));
+        // Every output report carries rumble data regardless of its id.
+        self.update_rumble(&output_report);
+        let res = match output_report_id {
+            OutputReportId::SubCommand => self.reply_to_subcommand(transport, &output_report).await,
+            OutputReportId::RumbleOnly => Ok(()),
+            OutputReportId::RequestIrNfcMcu => self.handle_mcu_request(&output_report),
+        };
+        match res {
+            // Malformed requests from the host are not fatal to the reader.
+            Err(
+                err @ ControllerProtocolError::Internal(ControllerProtocolInternalError::Report(_)),
+            ) => {
+                self.emit_event(Event::Warning(err));
+                Ok(())
             }
+            res => res,
         }
-        Ok(())
     }
 
     // Run writer operation using the given transport.
@@ -281,30 +499,84 @@ impl ControllerProtocol {
         // NOTE: Write hook may be used to notify controller state updater loop to continue.
         write_hook: Option<impl Future<Output = ()>>,
     ) -> Result<(), ControllerProtocolError> {
-        self.unpaused().await;
+        let paused_at = time::Instant::now();
+        let waited = self.unpaused().await;
         let now = time::Instant::now();
+        self.metrics.lock().unwrap().record_paced_write(
+            now,
+            waited.then(|| now - paused_at),
+            self.state.get().send_interval,
+        );
+        {
+            let mut sequencer = self.sequencer.lock().unwrap();
+            if sequencer.is_active() {
+                self.state
+                    .modify_controller_state(|state| sequencer.advance(now, state));
+            }
+        }
         let input_report = self.generate_input_report(None)?;
         self.handle_write(transport, input_report).await?;
+        self.sequencer.lock().unwrap().complete(now);
         if let Some(write_hook) = write_hook {
             write_hook.await;
         }
         let state = self.state.get();
         if state.send_interval == f64::INFINITY {
-            self.notify_writer_wake.notified().await
+            self.notify_writer_wake.notified().await;
+            self.metrics.lock().unwrap().last_paced_at = None;
         } else {
             let send_interval = Duration::from_secs_f64(state.send_interval);
             let elapsed = now.elapsed();
             let shim_delay = match send_interval.checked_sub(elapsed) {
                 Some(delay) => delay,
                 None => {
-                    let slow_duration = elapsed - send_interval;
-                    self.emit_event(Event::Warning(ControllerProtocolError::LaggedWrites(
-                        slow_duration,
-                    )));
+                    let lag = elapsed - send_interval;
+                    let stats = {
+                        let mut metrics = self.metrics.lock().unwrap();
+                        let stats = &mut metrics.lagged_writes;
+                        stats.lag = lag;
+                        stats.count += 1;
+                        stats.max_lag = stats.max_lag.max(lag);
+                        *stats
+                    };
+                    self.emit_event(Event::Log(LogType::WritesLagged(stats)));
                     return Ok(());
                 }
             };
-            let _ = time::timeout(shim_delay, self.notify_writer_wake.notified()).await;
+            // The next write is not paced if woken up before the interval.
+            if time::timeout(shim_delay, self.notify_writer_wake.notified())
+                .await
+                .is_ok()
+            {
+                self.metrics.lock().unwrap().last_paced_at = None;
+            }
+        }
+        Ok(())
+    }
+
+    fn update_rumble(&self, output_report: &OutputReport) {
+        let is_changed = self.state.modify(|state| {
+            if state.rumble_data[..] == *output_report.rumble_data() {
+                return false;
+            }
+            state
+                .rumble_data
+                .copy_from_slice(output_report.rumble_data());
+            true
+        });
+        if is_changed {
+            self.emit_event(Event::Rumble(output_report.rumble()));
+        }
+    }
+
+    fn handle_mcu_request(
+        &self,
+        output_report: &OutputReport,
+    ) -> Result<(), ControllerProtocolError> {
+        let request = output_report.mcu_request()?;
+        let res = self.modify_mcu(|mcu| mcu.process_request(request));
+        if let Err(err) = res {
+            self.emit_event(Event::Warning(ControllerProtocolError::from(err)));
         }
         Ok(())
     }
@@ -354,7 +626,12 @@ impl ControllerProtocol {
             }
             // If `None` is specified, try extracting it from the report mode.
             None => {
-                let interval = SendInterval::new(state.report_mode).to_byte();
+                let interval = SendInterval::with_config(
+                    state.report_mode,
+                    self.controller_type,
+                    &self.send_interval_config,
+                )
+                .to_byte();
                 match interval {
                     Some(interval) => state.send_interval = interval,
                     None => {
@@ -368,7 +645,8 @@ impl ControllerProtocol {
                     }
                 };
             }
-        })
+        });
+        self.update_snapshot();
     }
 
     async fn handle_write(
@@ -395,6 +673,7 @@ impl ControllerProtocol {
         if self.is_paused() {
             self.emit_event(Event::Warning(ControllerProtocolError::WriteWhilePaused));
         }
+        let started_at = time::Instant::now();
         transport_write
             .write(Bytes::copy_from_slice(input_report.as_buf()))
             .await
@@ -402,6 +681,10 @@ impl ControllerProtocol {
                 kind: err.kind(),
                 message: err.to_string(),
             })?;
+        let now = time::Instant::now();
+        let mut metrics = self.metrics.lock().unwrap();
+        metrics.write_latency.record(now - started_at);
+        metrics.reports_written.record(now);
         Ok(())
     }
 
@@ -438,7 +721,12 @@ impl ControllerProtocol {
                         None => 0,
                     };
                     input_report.set_timer(timer);
-                    input_report.set_misc();
+                    input_report.set_misc(
+                        state
+                            .controller_state
+                            .power_state()
+                            .to_byte(self.controller_type),
+                    );
                     input_report.set_button(state.controller_state.button_state().as_bytes());
                     input_report.set_analog_stick(
                         Some(state.controller_state.l_stick_state().to_buf()),
@@ -450,8 +738,7 @@ impl ControllerProtocol {
                         InputReportId::NfcIrMcu => {
                             input_report
                                 .set_6axis_data(state.controller_state.imu_state_mut().to_buf());
-                            // INFO: Sets empty data for now.
-                            input_report.set_ir_nfc_data(&[0xFF; 313])?;
+                            input_report.set_ir_nfc_data(&state.mcu.next_data())?;
                         }
                         InputReportId::Imu | InputReportId::Unknown1 | InputReportId::Unknown2 => {
                             input_report
@@ -470,54 +757,118 @@ impl ControllerProtocol {
         transport_write: &impl TransportWrite,
         output_report: &OutputReport,
     ) -> Result<(), ControllerProtocolError> {
-        let subcommand = match output_report.subcommand() {
-            Ok(subcommand) => subcommand,
+        let id = match output_report.subcommand_id() {
+            Ok(id) => id,
             Err(err) => {
                 self.emit_event(Event::Warning(ControllerProtocolError::from(err)));
                 // Silently continues the process after error logging.
                 return Ok(());
             }
         };
-        self.emit_event(Event::Log(LogType::SubcommandReceived(subcommand)));
+        if let SubcommandId::Known(subcommand) = id {
+            self.emit_event(Event::Log(LogType::SubcommandReceived(subcommand)));
+        }
         let sub_command_data = output_report.subcommand_data()?;
         let mut res_input_report = self.generate_input_report(Some(0x21))?;
-        match subcommand {
-            Subcommand::RequestDeviceInfo => {
+        if let Some(handler) = &self.subcommand_handler {
+            match handler.handle(id, sub_command_data, &mut res_input_report)? {
+                SubcommandReply::Default => {}
+                SubcommandReply::Reply => {
+                    self.handle_write(transport_write, res_input_report).await?;
+                    return Ok(());
+                }
+                SubcommandReply::Ignore => return Ok(()),
+            }
+        }
+        let request = match SubcommandRequest::with_bytes(id, sub_command_data) {
+            Ok(request) => request,
+            Err(err) => {
+                self.emit_event(Event::Warning(ControllerProtocolError::from(err)));
+                // Silently continues the process after error logging.
+                return Ok(());
+            }
+        };
+        match request {
+            SubcommandRequest::RequestDeviceInfo => {
                 self.command_request_device_info(&mut res_input_report)?;
             }
-            Subcommand::SetInputReportMode => {
-                self.command_set_input_report_mode(&mut res_input_report, &sub_command_data)?;
+            SubcommandRequest::SetInputReportMode { mode } => {
+                self.command_set_input_report_mode(&mut res_input_report, mode)?;
             }
-            Subcommand::TriggerButtonsElapsedTime => {
+            SubcommandRequest::TriggerButtonsElapsedTime => {
                 self.command_trigger_buttons_elapsed_time(&mut res_input_report)?;
             }
-            Subcommand::SetShipmentState => {
+            SubcommandRequest::SetShipmentState { .. } => {
                 self.command_set_shipment_state(&mut res_input_report)?;
             }
-            Subcommand::SpiFlashRead => {
-                self.command_spi_flash_read(&mut res_input_report, &sub_command_data)?;
+            SubcommandRequest::SpiFlashRead { offset, size } => {
+                self.command_spi_flash_read(&mut res_input_report, offset, size)?;
             }
-            Subcommand::SetNfcIrMcuConfig => {
-                self.command_set_nfc_ir_mcu_config(&mut res_input_report)?;
+            SubcommandRequest::SpiFlashWrite { offset, data } => {
+                self.command_spi_flash_write(&mut res_input_report, offset, &data)?;
             }
-            Subcommand::SetNfcIrMcuState => {
-                self.command_set_nfc_ir_mcu_state(&mut res_input_report, &sub_command_data)?;
+            SubcommandRequest::SpiSectorErase { offset } => {
+                self.command_spi_sector_erase(&mut res_input_report, offset)?;
             }
-            Subcommand::SetPlayerLights => {
-                self.command_set_player_lights(&mut res_input_report)?;
+            SubcommandRequest::SetNfcIrMcuConfig { args } => {
+                self.command_set_nfc_ir_mcu_config(&mut res_input_report, &args)?;
             }
-            Subcommand::Enable6AxisSensor => {
-                self.command_enable_6axis_sensor(&mut res_input_report)?;
+            SubcommandRequest::SetNfcIrMcuState { state } => {
+                self.command_set_nfc_ir_mcu_state(&mut res_input_report, state)?;
             }
-            Subcommand::EnableVibration => {
-                self.command_enable_vibration(&mut res_input_report, &sub_command_data)?;
+            SubcommandRequest::SetPlayerLights(player_lights) => {
+                self.command_set_player_lights(&mut res_input_report, player_lights)?;
             }
-            unsupported_subcommand => {
-                self.emit_event(Event::Warning(ControllerProtocolError::NotImplemented(
-                    format!("unsupported subcommand: \"{unsupported_subcommand}\", ignoring.",),
-                )));
-                return Ok(());
+            SubcommandRequest::GetPlayerLights => {
+                self.command_get_player_lights(&mut res_input_report)?;
+            }
+            SubcommandRequest::SetHomeLight(home_light) => {
+                self.command_set_home_light(&mut res_input_report, home_light)?;
+            }
+            SubcommandRequest::Enable6AxisSensor { enabled } => {
+                self.command_enable_6axis_sensor(&mut res_input_report, enabled)?;
+            }
+            SubcommandRequest::Set6AxisSensitivity {
+                gyro,
+                accel,
+                gyro_performance,
+                accel_filter,
+            } => {
+                self.command_set_6axis_sensitivity(
+                    &mut res_input_report,
+                    [gyro, accel, gyro_performance, accel_filter],
+                )?;
             }
+            SubcommandRequest::WriteTo6AxisRegisters { address, value } => {
+                self.command_write_to_6axis_registers(&mut res_input_report, address, value)?;
+            }
+            SubcommandRequest::Read6AxisRegisters { address, count } => {
+                self.command_read_6axis_registers(&mut res_input_report, address, count)?;
+            }
+            SubcommandRequest::EnableVibration { enabled } => {
+                self.command_enable_vibration(&mut res_input_report, enabled)?;
+            }
+            SubcommandRequest::Other {
+                id: unsupported_subcommand,
+                ..
+            } => match self.unsupported_subcommand {
+                UnsupportedSubcommandPolicy::GenericAck => {
+                    self.emit_event(Event::Warning(ControllerProtocolError::NotImplemented(
+                        format!("unsupported subcommand: \"{unsupported_subcommand}\", replying with a generic ack."),
+                    )));
+                    res_input_report.set_ack(0x80);
+                    res_input_report.set_response_subcommand_id(unsupported_subcommand);
+                }
+                UnsupportedSubcommandPolicy::Ignore => {
+                    self.emit_event(Event::Warning(ControllerProtocolError::NotImplemented(
+                        format!("unsupported subcommand: \"{unsupported_subcommand}\", ignoring."),
+                    )));
+                    return Ok(());
+                }
+            },
+        }
+        if let Some(handler) = &self.subcommand_handler {
+            handler.extend(id, sub_command_data, &mut res_input_report)?;
         }
         self.handle_write(transport_write, res_input_report).await?;
         Ok(())
@@ -528,7 +879,18 @@ impl ControllerProtocol {
         input_report: &mut InputReport,
     ) -> Result<(), ControllerProtocolError> {
         input_report.set_ack(0x82);
-        input_report.sub_0x02_device_info(*self.dev_addr, None, self.controller_type)?;
+        let use_spi_colors = self.state.modify(|state| {
+            state
+                .spi_flash
+                .as_ref()
+                .is_some_and(|spi_flash| spi_flash.colors().is_some())
+        });
+        input_report.sub_0x02_device_info(
+            *self.dev_addr,
+            None,
+            self.controller_type,
+            use_spi_colors,
+        )?;
         Ok(())
     }
 
@@ -544,107 +906,124 @@ impl ControllerProtocol {
     fn command_spi_flash_read(
         &self,
         input_report: &mut InputReport,
-        subcommand_reply_data: &[u8],
+        offset: u32,
+        size: u8,
     ) -> Result<(), ControllerProtocolError> {
         input_report.set_ack(0x90);
-        let mut offset: u64 = 0;
-        let mut place: u64 = 1;
-        for i in 0..4 {
-            offset += subcommand_reply_data[i] as u64 * place;
-            place *= 0x100;
-        }
-        let size = subcommand_reply_data[4];
         let state = self.state.get();
-        match state.spi_flash {
-            Some(spi_flash) => {
-                // FIXME: temporarily disabled due to custom spi flash responses
-                // let spi_flash_data = &spi_flash[(offset as usize)..(offset + size as u64) as usize];
-                // input_report.sub_0x10_spi_flash_read(offset, size, spi_flash_data)?;
-                match &subcommand_reply_data[..2] {
-                    b"\x00\x60" => input_report.sub_0x10_spi_flash_read(
-                        offset,
-                        size,
-                        &[
-                            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
-                            0xff, 0xff, 0xff, 0xff,
-                        ],
-                    )?,
-                    b"\x50\x60" => input_report.sub_0x10_spi_flash_read(
-                        offset,
-                        size,
-                        &[
-                            0xbc, 0x11, 0x42, 0x75, 0xa9, 0x28, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
-                            0xff, 0xff, 0xff, 0xff,
-                        ],
-                    )?,
-                    b"\x80\x60" => input_report.sub_0x10_spi_flash_read(
-                        offset,
-                        size,
-                        &[
-                            0x50, 0xfd, 0x00, 0x00, 0xc6, 0x0f, 0x0f, 0x30, 0x61, 0x96, 0x30, 0xf3,
-                            0xd4, 0x14, 0x54, 0x41, 0x15, 0x54, 0xc7, 0x77, 0x99, 0xc3, 0x33, 0x66,
-                            0x3,
-                        ],
-                    )?,
-                    b"\x98\x60" => input_report.sub_0x10_spi_flash_read(
-                        offset,
-                        size,
-                        &[
-                            0x0f, 0x30, 0x61, 0x96, 0x30, 0xf3, 0xd4, 0x14, 0x54, 0x41, 0x15, 0x54,
-                            0xc7, 0x77, 0x99, 0xc3, 0x33, 0x66, 0x3,
-                        ],
-                    )?,
-                    b"\x3d\x60" => input_report.sub_0x10_spi_flash_read(
-                        offset,
-                        size,
-                        &[
-                            0xba, 0x15, 0x62, 0x11, 0xb8, 0x7f, 0x29, 0x06, 0x5b, 0xff, 0xe7, 0x7e,
-                            0x0e, 0x36, 0x56, 0x9e, 0x85, 0x60, 0xff, 0x32, 0x32, 0x32, 0xff, 0xff,
-                            0xff,
-                        ],
-                    )?,
-                    b"\x10\x80" => input_report.sub_0x10_spi_flash_read(
-                        offset,
-                        size,
-                        &[
-                            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
-                            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xb2,
-                            0xa1,
-                        ],
-                    )?,
-                    b"\x28\x80" => input_report.sub_0x10_spi_flash_read(
-                        offset,
-                        size,
-                        &[
-                            0xbe, 0xff, 0x3e, 0x00, 0xf0, 0x01, 0x00, 0x40, 0x00, 0x40, 0x00, 0x40,
-                            0xfe, 0xff, 0xff, 0xff, 0x08, 0x00, 0xe7, 0x3b, 0xe7, 0x3b, 0xe7, 0x3b,
-                        ],
-                    )?,
-                    _ => {
-                        self.emit_event(Event::Warning(ControllerProtocolError::NotImplemented(
-                            format!(
-                                "unsupported spi flash read command: \"{:?}\", ignoring.",
-                                &subcommand_reply_data[11..13]
-                            ),
-                        )));
-                    }
+        let res = match state.spi_flash {
+            Some(spi_flash) => match spi_flash.read(offset, size) {
+                Some(spi_flash_data) => {
+                    input_report.sub_0x10_spi_flash_read(offset, size, spi_flash_data)
                 }
-            }
+                None => Err(ReportError::OutOfBounds),
+            },
             None => {
                 let zeroed_spi_flash_data: Vec<u8> = vec![0; size as usize];
-                input_report.sub_0x10_spi_flash_read(offset, size, &zeroed_spi_flash_data)?;
+                input_report.sub_0x10_spi_flash_read(offset, size, &zeroed_spi_flash_data)
             }
+        };
+        if res.is_err() {
+            self.emit_event(Event::Warning(ControllerProtocolError::Invariant(format!(
+                "spi flash read out of bounds: offset \"{offset:#X}\", size \"{size:#X}\", replying with a nack."
+            ))));
+            input_report.set_ack(0x00);
+            input_report.set_response_subcommand(Subcommand::SpiFlashRead)?;
+        }
+        Ok(())
+    }
+
+    fn command_spi_flash_write(
+        &self,
+        input_report: &mut InputReport,
+        offset: u32,
+        data: &[u8],
+    ) -> Result<(), ControllerProtocolError> {
+        input_report.set_ack(0x80);
+        let res = self.modify_spi_flash(|spi_flash| spi_flash.write(offset, data));
+        input_report.sub_0x11_spi_flash_write(res.is_ok())?;
+        match res {
+            Ok(_) => self.emit_event(Event::Log(LogType::SpiFlashWritten {
+                offset,
+                size: data.len() as u32,
+            })),
+            Err(err) => self.emit_event(Event::Warning(err)),
         }
         Ok(())
     }
 
+    fn command_spi_sector_erase(
+        &self,
+        input_report: &mut InputReport,
+        offset: u32,
+    ) -> Result<(), ControllerProtocolError> {
+        input_report.set_ack(0x80);
+        let res = self.modify_spi_flash(|spi_flash| spi_flash.erase_sector(offset));
+        input_report.sub_0x12_spi_sector_erase(res.is_ok())?;
+        match res {
+            Ok(_) => self.emit_event(Event::Log(LogType::SpiFlashWritten {
+                offset: offset - offset % SECTOR_SIZE,
+                size: SECTOR_SIZE,
+            })),
+            Err(err) => self.emit_event(Event::Warning(err)),
+        }
+        Ok(())
+    }
+
+    // Applies changes to the SPI flash, then reloads the stick calibration in
+    // case the host has written user calibration data.
+    fn modify_spi_flash(
+        &self,
+        f: impl FnOnce(&mut SpiFlash) -> Result<(), SpiFlashError>,
+    ) -> Result<(), ControllerProtocolError> {
+        self.state.modify(|state| {
+            let Some(spi_flash) = state.spi_flash.as_mut() else {
+                return Err(ControllerProtocolError::from(SpiFlashError::WriteProtected));
+            };
+            f(spi_flash)?;
+            let l_calibration = StickCalibration::with_left_stick_bytes(
+                match spi_flash.user_l_stick_calibration() {
+                    Some(calibration_data) => calibration_data,
+                    None => spi_flash.factory_l_stick_calibration(),
+                },
+            );
+            let r_calibration = StickCalibration::with_right_stick_bytes(
+                match spi_flash.user_r_stick_calibration() {
+                    Some(calibration_data) => calibration_data,
+                    None => spi_flash.factory_r_stick_calibration(),
+                },
+            );
+            if let Some(l_calibration) = l_calibration {
+                state
+                    .controller_state
+                    .l_stick_state_mut()
+                    .set_calibration(l_calibration)?;
+            }
+            if let Some(r_calibration) = r_calibration {
+                state
+                    .controller_state
+                    .r_stick_state_mut()
+                    .set_calibration(r_calibration)?;
+            }
+            Ok(())
+        })
+    }
+
     fn command_set_input_report_mode(
         &self,
         input_report: &mut InputReport,
-        subcommand_reply_data: &[u8],
+        mode: u8,
     ) -> Result<(), ControllerProtocolError> {
+        // An unknown mode would fail every input report after it.
+        if InputReportId::from_byte(mode).is_none() {
+            self.emit_event(Event::Warning(
+                ControllerProtocolError::UnknownInputReportMode,
+            ));
+            input_report.set_ack(0x00);
+            input_report.set_response_subcommand(Subcommand::SetInputReportMode)?;
+            return Ok(());
+        }
         let state = self.state.get();
-        let mode = subcommand_reply_data[0];
         if let Some(report_mode) = state.report_mode {
             if report_mode == mode {
                 self.emit_event(Event::Warning(
@@ -667,7 +1046,10 @@ impl ControllerProtocol {
         // HACK: We assume this command is only used during pairing, sets values
         // and let the Switch to assign a player number.
         match self.controller_type {
-            // INFO: Currently we don't support a combined JoyCon.
+            // INFO: Currently we don't support a combined JoyCon. The Switch
+            // tells the Joy-Cons of a pair apart by their addresses, while an
+            // adapter presents a single address, so the pair needs a
+            // connection from each of two adapters.
             ControllerType::JoyConL | ControllerType::JoyConR => input_report
                 .sub_0x04_trigger_buttons_elapsed_time(&[
                     TriggerButtonsElapsedTimeCommand::SLeftTrigger(3000),
@@ -686,41 +1068,123 @@ impl ControllerProtocol {
     fn command_enable_6axis_sensor(
         &self,
         input_report: &mut InputReport,
+        enabled: bool,
     ) -> Result<(), ControllerProtocolError> {
         input_report.set_ack(0x80);
         input_report.set_response_subcommand(Subcommand::Enable6AxisSensor)?;
+        let is_changed = self.state.modify(|state| {
+            let is_changed = state.imu_enabled != enabled;
+            state.imu_enabled = enabled;
+            is_changed
+        });
+        if is_changed {
+            self.emit_event(Event::Log(LogType::ImuToggled { enabled }));
+        }
         Ok(())
     }
 
+    fn command_set_6axis_sensitivity(
+        &self,
+        input_report: &mut InputReport,
+        sensitivity: [u8; 4],
+    ) -> Result<(), ControllerProtocolError> {
+        input_report.set_ack(0x80);
+        input_report.set_response_subcommand(Subcommand::Set6AxisSensitivity)?;
+        let res =
+            self.modify_imu_registers(|imu_registers| imu_registers.set_sensitivity(sensitivity));
+        if res.is_none() {
+            self.emit_event(Event::Warning(ControllerProtocolError::Invariant(format!(
+                "unknown 6-axis sensitivity: {sensitivity:X?}, ignoring."
+            ))));
+        }
+        Ok(())
+    }
+
+    fn command_write_to_6axis_registers(
+        &self,
+        input_report: &mut InputReport,
+        address: u8,
+        value: u8,
+    ) -> Result<(), ControllerProtocolError> {
+        input_report.set_ack(0x80);
+        input_report.set_response_subcommand(Subcommand::WriteTo6AxisRegisters)?;
+        let res = self.modify_imu_registers(|imu_registers| imu_registers.write(address, value));
+        if res.is_none() {
+            self.emit_event(Event::Warning(ControllerProtocolError::Invariant(format!(
+                "6-axis register write out of bounds: address \"{address:#X}\", ignoring."
+            ))));
+        }
+        Ok(())
+    }
+
+    fn command_read_6axis_registers(
+        &self,
+        input_report: &mut InputReport,
+        address: u8,
+        count: u8,
+    ) -> Result<(), ControllerProtocolError> {
+        input_report.set_ack(0xC0);
+        let imu_registers = self.state.modify(|state| state.imu_registers.clone());
+        let Some(data) = imu_registers.read(address, count) else {
+            self.emit_event(Event::Warning(ControllerProtocolError::Invariant(format!(
+                "6-axis register read out of bounds: address \"{address:#X}\", count \"{count:#X}\", ignoring."
+            ))));
+            return Ok(());
+        };
+        input_report.sub_0x43_read_6axis_registers(address, count, data)?;
+        Ok(())
+    }
+
+    // Applies changes to the 6-axis registers, then feeds the sensitivity
+    // ranges back into the IMU state.
+    fn modify_imu_registers<R>(&self, f: impl FnOnce(&mut ImuRegisters) -> Option<R>) -> Option<R> {
+        self.state.modify(|state| {
+            let ret = f(&mut state.imu_registers)?;
+            let accel_sensitivity = state.imu_registers.accel_sensitivity();
+            let gyro_sensitivity = state.imu_registers.gyro_sensitivity();
+            let imu_state = state.controller_state.imu_state_mut();
+            imu_state.set_accel_sensitivity(accel_sensitivity);
+            imu_state.set_gyro_sensitivity(gyro_sensitivity);
+            Some(ret)
+        })
+    }
+
     fn command_enable_vibration(
         &self,
         input_report: &mut InputReport,
-        subcommand_reply_data: &[u8],
+        enabled: bool,
     ) -> Result<(), ControllerProtocolError> {
-        let command = subcommand_reply_data[0];
-        match command {
-            // If it's 0x01, then we shell slow down the send frequency.
-            0x01 => self.set_send_interval(Some(SendInterval::default_byte())),
+        let is_changed = self.state.modify(|state| {
+            let is_changed = state.vibration_enabled != enabled;
+            state.vibration_enabled = enabled;
+            is_changed
+        });
+        if is_changed {
+            self.emit_event(Event::Log(LogType::VibrationToggled { enabled }));
+        }
+        if enabled {
+            // If it's enabled, then we shell slow down the send frequency.
+            self.set_send_interval(Some(SendInterval::default_byte()));
+        } else {
             // Otherwise, we can release it to match with the pace of `report_mode`.
             //
             // Also, we toggle `is_pairing` flag to `true` if not toggled previously.
-            _ => {
-                // FIXME: still fragile...
-                // let pairing_toggled = self.state.modify(|state| {
-                //     if state.is_pairing {
-                //         state.is_pairing = false;
-                //         true
-                //     } else {
-                //         false
-                //     }
-                // });
-                // if pairing_toggled {
-                //     self.set_report_mode(None);
-                //     self.emit_event(Event::Log(LogType::PairingSuccess));
-                // } else {
-                self.set_send_interval(None)
-                // }
-            }
+            //
+            // FIXME: still fragile...
+            // let pairing_toggled = self.state.modify(|state| {
+            //     if state.is_pairing {
+            //         state.is_pairing = false;
+            //         true
+            //     } else {
+            //         false
+            //     }
+            // });
+            // if pairing_toggled {
+            //     self.set_report_mode(None);
+            //     self.emit_event(Event::Log(LogType::PairingSuccess));
+            // } else {
+            self.set_send_interval(None);
+            // }
         }
         input_report.set_ack(0x80);
         input_report.set_response_subcommand(Subcommand::EnableVibration)?;
@@ -730,32 +1194,56 @@ impl ControllerProtocol {
     fn command_set_nfc_ir_mcu_config(
         &self,
         input_report: &mut InputReport,
+        args: &[u8],
     ) -> Result<(), ControllerProtocolError> {
         input_report.set_ack(0xA0);
         input_report.set_response_subcommand(Subcommand::SetNfcIrMcuConfig)?;
-        input_report.as_mut()[16..50].copy_from_slice(&[
-            0x01, 0x00, 0xFF, 0x00, 0x08, 0x00, 0x1B, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0xC8,
-        ]);
+        let reply =
+            self.modify_mcu(|mcu| mcu.configure(args).map_err(|err| (err, mcu.config_reply())));
+        let reply = match reply {
+            Ok(reply) => reply,
+            Err((err, reply)) => {
+                // Replies with the current status of the MCU.
+                self.emit_event(Event::Warning(ControllerProtocolError::from(err)));
+                reply
+            }
+        };
+        input_report.as_mut()[16..50].copy_from_slice(&reply);
         Ok(())
     }
 
+    // Applies changes to the MCU, and notifies the mode changes.
+    fn modify_mcu<R>(&self, f: impl FnOnce(&mut Mcu) -> R) -> R {
+        let (ret, mode) = self.state.modify(|state| {
+            let mode = state.mcu.mode();
+            let ret = f(&mut state.mcu);
+            let new_mode = state.mcu.mode();
+            (ret, (mode != new_mode).then_some(new_mode))
+        });
+        if let Some(mode) = mode {
+            self.emit_event(Event::Log(LogType::McuModeChanged(mode)));
+        }
+        ret
+    }
+
     fn command_set_nfc_ir_mcu_state(
         &self,
         input_report: &mut InputReport,
-        subcommand_reply_data: &[u8],
+        mcu_state: u8,
     ) -> Result<(), ControllerProtocolError> {
-        let command = subcommand_reply_data[0];
-        match command {
+        match mcu_state {
             // Resume + Suspend
             0x01 | 0x00 => {
                 input_report.set_ack(0x80);
                 input_report.set_response_subcommand(Subcommand::SetNfcIrMcuState)?;
+                self.modify_mcu(|mcu| match mcu_state {
+                    0x01 => mcu.resume(),
+                    _ => mcu.suspend(),
+                });
             }
             _ => {
                 self.emit_event(Event::Warning(ControllerProtocolError::NotImplemented(
-                    format!("command \"{command}\" for Subcommand NFC IR is not implemented.",),
+                    format!("command \"{mcu_state}\" for Subcommand NFC IR is not implemented.",),
                 )));
             }
         }
@@ -765,10 +1253,47 @@ impl ControllerProtocol {
     fn command_set_player_lights(
         &self,
         input_report: &mut InputReport,
+        player_lights: PlayerLights,
     ) -> Result<(), ControllerProtocolError> {
         input_report.set_ack(0x80);
         input_report.set_response_subcommand(Subcommand::SetPlayerLights)?;
         self.set_writer_ready();
+        let is_changed = self.state.modify(|state| {
+            let is_changed = state.player_lights != player_lights;
+            state.player_lights = player_lights;
+            is_changed
+        });
+        if is_changed {
+            self.emit_event(Event::PlayerLights(player_lights));
+        }
+        Ok(())
+    }
+
+    fn command_get_player_lights(
+        &self,
+        input_report: &mut InputReport,
+    ) -> Result<(), ControllerProtocolError> {
+        input_report.set_ack(0xB0);
+        let player_lights = self.state.modify(|state| state.player_lights);
+        input_report.sub_0x31_player_lights(player_lights.to_byte())?;
+        Ok(())
+    }
+
+    fn command_set_home_light(
+        &self,
+        input_report: &mut InputReport,
+        home_light: HomeLight,
+    ) -> Result<(), ControllerProtocolError> {
+        input_report.set_ack(0x80);
+        input_report.set_response_subcommand(Subcommand::SetHomeLight)?;
+        let is_changed = self.state.modify(|state| {
+            let is_changed = state.home_light.as_ref() != Some(&home_light);
+            state.home_light = Some(home_light.clone());
+            is_changed
+        });
+        if is_changed {
+            self.emit_event(Event::HomeLight(home_light));
+        }
         Ok(())
     }
 
@@ -797,11 +1322,47 @@ impl ControllerProtocol {
         *self.paused_tx.borrow()
     }
 
-    async fn unpaused(&self) {
+    // Returns whether it has waited for the protocol to be unpaused.
+    async fn unpaused(&self) -> bool {
         let mut rx = self.paused_tx.subscribe();
+        let mut waited = false;
         while *rx.borrow() {
+            waited = true;
             rx.changed().await.unwrap();
         }
+        waited
+    }
+
+    // Returns a copy of the current SPI flash image, e.g. to persist changes
+    // made by the host. The colors of the profile are kept as they were.
+    pub fn spi_flash(&self) -> Option<SpiFlash> {
+        let mut spi_flash = self.state.modify(|state| state.spi_flash.clone())?;
+        if let Some(raw_colors) = &self.profile_raw_colors {
+            spi_flash.set_raw_colors(raw_colors);
+        }
+        Some(spi_flash)
+    }
+
+    // Puts the NFC tag (e.g. amiibo) on the virtual reader, or takes it off if `None`.
+    pub fn set_nfc_tag(&self, nfc_tag: Option<NfcTag>) {
+        self.state.modify(|state| state.mcu.set_nfc_tag(nfc_tag));
+    }
+
+    pub fn nfc_tag(&self) -> Option<NfcTag> {
+        self.state.modify(|state| state.mcu.nfc_tag().cloned())
+    }
+
+    // Pushes the grayscale frame to be sent as the IR camera image.
+    pub fn push_ir_frame(&self, frame: IrFrame) {
+        self.state.modify(|state| state.mcu.push_ir_frame(frame));
+    }
+
+    pub fn player_lights(&self) -> PlayerLights {
+        self.state.modify(|state| state.player_lights)
+    }
+
+    pub fn home_light(&self) -> Option<HomeLight> {
+        self.state.modify(|state| state.home_light.clone())
     }
 
     // Listen for the protocol events.
@@ -818,14 +1379,469 @@ impl ControllerProtocol {
 pub enum Event {
     Log(LogType),
     Warning(ControllerProtocolError),
+    Rumble(Rumble),
+    PlayerLights(PlayerLights),
+    HomeLight(HomeLight),
 }
 
 #[derive(Clone, Debug, Display, Eq, PartialEq, Ord, PartialOrd, Hash, IntoStaticStr)]
 pub enum LogType {
     PairingEnded,
     SubcommandReceived(Subcommand),
+    // Emitted when the host modifies the SPI flash, so that the caller can
+    // persist the image retrieved from `spi_flash()`.
+    SpiFlashWritten { offset: u32, size: u32 },
+    ReportModeChanged(u8),
+    // `None` if the writer waits to be woken up instead of the interval.
+    SendIntervalChanged(Option<Duration>),
+    ImuToggled { enabled: bool },
+    VibrationToggled { enabled: bool },
+    McuModeChanged(McuMode),
+    WritesLagged(LaggedWriteStats),
+}
+
+// Statistics of the writes which have taken longer than the send interval.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct LaggedWriteStats {
+    // How much the last write has exceeded the send interval.
+    pub lag: Duration,
+    pub max_lag: Duration,
+    // Number of the lagged writes since the protocol is created.
+    pub count: u64,
 }
 
 impl Event {
     setup_event!(Event);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ControllerProtocol, ControllerProtocolConfig, ControllerProtocolError, Event, LogType,
+    };
+    use crate::{
+        capture::{CaptureRecord, Direction},
+        controller::{
+            color::{ControllerColors, Rgb},
+            handler::{SubcommandHandler, SubcommandReply, UnsupportedSubcommandPolicy},
+            interval::SendInterval,
+            mcu::McuMode,
+            report::{
+                input::InputReport,
+                subcommand::{Subcommand, SubcommandId},
+            },
+            sequence::{Hold, InputSequence},
+            spi_flash::SpiFlash,
+            state::button::ButtonKey,
+        },
+        replay::{ReplayConfig, ReplayTransport},
+        test_utils::subcommand_report,
+    };
+    use bytes::Bytes;
+    use proptest::prelude::*;
+    use std::{sync::Arc, time::SystemTime};
+    use tokio::time::{self, Duration};
+
+    fn replay(reports: Vec<Vec<u8>>) -> ReplayTransport {
+        let records = reports
+            .into_iter()
+            .map(|report| CaptureRecord {
+                direction: Direction::Received,
+                timestamp: SystemTime::UNIX_EPOCH,
+                report: Bytes::from(report),
+            })
+            .collect();
+        ReplayTransport::new(
+            records,
+            ReplayConfig {
+                speed: f64::INFINITY,
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn watch_snapshot() {
+        let protocol = ControllerProtocol::new(ControllerProtocolConfig::default()).unwrap();
+        let mut snapshot_rx = protocol.watch_snapshot();
+        assert!(snapshot_rx.borrow().is_pairing);
+        assert_eq!(snapshot_rx.borrow().report_mode, None);
+
+        // Set input report mode: standard full mode
+        let transport = replay(vec![
+            subcommand_report(&[0x03, 0x30]),
+            subcommand_report(&[0x03, 0x30]),
+        ]);
+        protocol.process_read(&transport).await.unwrap();
+        assert!(snapshot_rx.has_changed().unwrap());
+        let snapshot = snapshot_rx.borrow_and_update().clone();
+        assert_eq!(snapshot, protocol.snapshot());
+        assert_eq!(snapshot.report_mode, Some(0x30));
+        assert_eq!(snapshot.send_interval, SendInterval::default_byte());
+        // Setting the same mode again does not fire.
+        protocol.process_read(&transport).await.unwrap();
+        assert!(!snapshot_rx.has_changed().unwrap());
+
+        // Pressing A ends the pairing, which speeds up the reports.
+        protocol
+            .modify_controller_state(|state| {
+                state.button_state_mut().set_button(ButtonKey::A, true)
+            })
+            .await
+            .unwrap();
+        protocol
+            .process_write(&transport, None::<std::future::Ready<()>>)
+            .await
+            .unwrap();
+        assert!(snapshot_rx.has_changed().unwrap());
+        let snapshot = snapshot_rx.borrow_and_update().clone();
+        assert!(!snapshot.is_pairing);
+        assert_eq!(snapshot.send_interval, 1.0 / 120.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn record_pacing_jitter() {
+        let protocol = ControllerProtocol::new(ControllerProtocolConfig::default()).unwrap();
+        // Set input report mode: standard full mode
+        let transport = replay(vec![subcommand_report(&[0x03, 0x30])]);
+        protocol.process_read(&transport).await.unwrap();
+        for _ in 0..3 {
+            protocol
+                .process_write(&transport, None::<std::future::Ready<()>>)
+                .await
+                .unwrap();
+        }
+        assert_eq!(protocol.metrics().pacing_jitter.count(), 2);
+        assert!(protocol.metrics().pacing_jitter.max() < Duration::from_millis(1));
+
+        // Woken up before the interval, e.g. by a new report mode.
+        tokio::join!(
+            async {
+                protocol
+                    .process_write(&transport, None::<std::future::Ready<()>>)
+                    .await
+                    .unwrap();
+            },
+            async {
+                time::sleep(Duration::from_millis(1)).await;
+                protocol.notify_writer_wake.notify_waiters();
+            },
+        );
+        protocol
+            .process_write(&transport, None::<std::future::Ready<()>>)
+            .await
+            .unwrap();
+        assert_eq!(protocol.metrics().pacing_jitter.count(), 3);
+        assert!(protocol.metrics().pacing_jitter.max() < Duration::from_millis(1));
+    }
+
+    #[tokio::test]
+    async fn emit_host_feedback() {
+        let protocol = ControllerProtocol::new(ControllerProtocolConfig::default()).unwrap();
+        let mut event_rx = protocol.events().await.unwrap();
+        let transport = replay(vec![
+            // Set input report mode: standard full mode
+            subcommand_report(&[0x03, 0x30]),
+            // Enable 6-axis sensor
+            subcommand_report(&[0x40, 0x01]),
+            // Enable vibration
+            subcommand_report(&[0x48, 0x01]),
+            // Set NFC/IR MCU state: resume
+            subcommand_report(&[0x22, 0x01]),
+        ]);
+        while !transport.is_finished() {
+            protocol.process_read(&transport).await.unwrap();
+        }
+        let mut expected = vec![
+            LogType::ReportModeChanged(0x30),
+            LogType::ImuToggled { enabled: true },
+            LogType::VibrationToggled { enabled: true },
+            LogType::McuModeChanged(McuMode::Standby),
+        ];
+        time::timeout(Duration::from_secs(1), async {
+            while !expected.is_empty() {
+                match event_rx.recv().await {
+                    Some(Event::Log(log)) => expected.retain(|expected| *expected != log),
+                    Some(_) => {}
+                    None => break,
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(expected, []);
+    }
+
+    #[tokio::test]
+    async fn keep_profile_colors() {
+        let colors = ControllerColors::new(Rgb::from_u32(0x323232), Rgb::from_u32(0xFFFFFF));
+        let protocol = ControllerProtocol::new(ControllerProtocolConfig {
+            colors: Some(colors),
+            ..Default::default()
+        })
+        .unwrap();
+        // Serves the colors to the host, but leaves the profile as it was.
+        let served = protocol.state.modify(|state| state.spi_flash.clone());
+        assert_eq!(served.unwrap().colors(), Some(colors));
+        assert_eq!(&protocol.spi_flash().unwrap()[..], &SpiFlash::new()[..]);
+    }
+
+    // Replies to the device info by itself, ignores the vibration, and marks
+    // the other replies with a custom ACK.
+    #[derive(Debug)]
+    struct TestHandler;
+
+    impl SubcommandHandler for TestHandler {
+        fn handle(
+            &self,
+            id: SubcommandId,
+            _data: &[u8],
+            input_report: &mut InputReport,
+        ) -> Result<SubcommandReply, ControllerProtocolError> {
+            Ok(match id {
+                SubcommandId::Known(Subcommand::RequestDeviceInfo) => {
+                    input_report.set_ack(0x82);
+                    input_report.set_response_subcommand_id(id);
+                    SubcommandReply::Reply
+                }
+                SubcommandId::Known(Subcommand::EnableVibration) => SubcommandReply::Ignore,
+                _ => SubcommandReply::Default,
+            })
+        }
+
+        fn extend(
+            &self,
+            _id: SubcommandId,
+            _data: &[u8],
+            input_report: &mut InputReport,
+        ) -> Result<(), ControllerProtocolError> {
+            input_report.set_ack(0xC0);
+            Ok(())
+        }
+    }
+
+    // Pairs of the ACK and the subcommand ID of the replies written.
+    fn replies(transport: &ReplayTransport) -> Vec<(u8, u8)> {
+        transport
+            .writes()
+            .iter()
+            .filter(|report| report[1] == 0x21)
+            .map(|report| (report[14], report[15]))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn reply_with_subcommand_handler() {
+        let protocol = ControllerProtocol::new(ControllerProtocolConfig {
+            subcommand_handler: Some(Arc::new(TestHandler)),
+            ..Default::default()
+        })
+        .unwrap();
+        let transport = replay(vec![
+            // Request device info
+            subcommand_report(&[0x02]),
+            // Enable vibration
+            subcommand_report(&[0x48, 0x01]),
+            // Set player lights
+            subcommand_report(&[0x30, 0x01]),
+            // Unknown subcommand
+            subcommand_report(&[0x5A, 0x01]),
+        ]);
+        while !transport.is_finished() {
+            protocol.process_read(&transport).await.unwrap();
+        }
+        assert_eq!(
+            replies(&transport),
+            [
+                // Replied by the handler, without the device info.
+                (0x82, 0x02),
+                // Built-in reply, extended by the handler.
+                (0xC0, 0x30),
+                // Generic ACK with the raw ID echoed, extended as well.
+                (0xC0, 0x5A),
+            ]
+        );
+        assert!(transport.writes()[0][16..].iter().all(|byte| *byte == 0x00));
+    }
+
+    #[tokio::test]
+    async fn reply_to_unsupported_subcommand() {
+        let transport = replay(vec![subcommand_report(&[0x5A, 0x01])]);
+        let protocol = ControllerProtocol::new(ControllerProtocolConfig::default()).unwrap();
+        protocol.process_read(&transport).await.unwrap();
+        assert_eq!(replies(&transport), [(0x80, 0x5A)]);
+
+        let transport = replay(vec![subcommand_report(&[0x5A, 0x01])]);
+        let protocol = ControllerProtocol::new(ControllerProtocolConfig {
+            unsupported_subcommand: UnsupportedSubcommandPolicy::Ignore,
+            ..Default::default()
+        })
+        .unwrap();
+        protocol.process_read(&transport).await.unwrap();
+        assert_eq!(replies(&transport), []);
+    }
+
+    #[tokio::test]
+    async fn reply_to_spi_flash_read_out_of_bounds() {
+        let transport = replay(vec![
+            // Larger than a reply can carry.
+            subcommand_report(&[0x10, 0x00, 0x60, 0x00, 0x00, 0x1E]),
+            // Past the end of the flash.
+            subcommand_report(&[0x10, 0xF0, 0xFF, 0x07, 0x00, 0x18]),
+            subcommand_report(&[0x10, 0x00, 0x60, 0x00, 0x00, 0x10]),
+        ]);
+        let protocol = ControllerProtocol::new(ControllerProtocolConfig::default()).unwrap();
+        while !transport.is_finished() {
+            protocol.process_read(&transport).await.unwrap();
+        }
+        assert_eq!(
+            replies(&transport),
+            [(0x00, 0x10), (0x00, 0x10), (0x90, 0x10)]
+        );
+    }
+
+    #[tokio::test]
+    async fn write_default_report() {
+        let transport = replay(vec![
+            // Set input report mode: simple HID mode
+            subcommand_report(&[0x03, 0x3F]),
+            subcommand_report(&[0x30, 0x01]),
+        ]);
+        let protocol = ControllerProtocol::new(ControllerProtocolConfig::default()).unwrap();
+        while !transport.is_finished() {
+            protocol.process_read(&transport).await.unwrap();
+        }
+        protocol
+            .process_write(&transport, None::<std::future::Ready<()>>)
+            .await
+            .unwrap();
+        let writes = transport.writes();
+        let report = writes.last().unwrap();
+        assert_eq!(report[1], 0x3F);
+        assert_eq!(report[2..5], [0x28, 0xCA, 0x08]);
+    }
+
+    #[tokio::test]
+    async fn send_sequence() {
+        let protocol = ControllerProtocol::new(ControllerProtocolConfig::default()).unwrap();
+        // Set input report mode: standard full mode
+        let transport = replay(vec![subcommand_report(&[0x03, 0x30])]);
+        protocol.process_read(&transport).await.unwrap();
+        let sequence = InputSequence::new().press(ButtonKey::A, Hold::Reports(3));
+        let (res, sent) = tokio::join!(
+            async {
+                protocol.send_sequence(sequence).await?;
+                Ok::<_, ControllerProtocolError>(transport.writes().len())
+            },
+            async {
+                for _ in 0..6 {
+                    protocol
+                        .process_write(&transport, None::<std::future::Ready<()>>)
+                        .await
+                        .unwrap();
+                }
+                transport.writes()
+            },
+        );
+        let pressed: Vec<bool> = sent
+            .iter()
+            .filter(|report| report[1] == 0x30)
+            .map(|report| report[4] & 0x08 != 0)
+            .collect();
+        assert_eq!(pressed, [true, true, true, false, false, false]);
+        // Resolved right after the release has been sent, along with the
+        // subcommand reply.
+        assert_eq!(res.unwrap(), 5);
+        assert!(!protocol
+            .controller_state()
+            .button_state()
+            .is_button_set(ButtonKey::A));
+    }
+
+    // Output reports with a valid header, which reach the subcommand and MCU
+    // handlers with arbitrary data. Short data is favored, as well as the
+    // subcommands known to the protocol, the report modes along with the
+    // player lights that start the writer, and the SPI flash reads within
+    // the flash.
+    fn output_report() -> impl Strategy<Value = Vec<u8>> {
+        let subcommand = (0x00u8..0x53).prop_map(|byte| vec![0x01, byte]);
+        let report_mode = prop::sample::select(vec![0x21u8, 0x30, 0x31, 0x3F])
+            .prop_map(|mode| vec![0x01, 0x03, mode]);
+        let player_lights = Just(vec![0x01, 0x30]);
+        let spi_flash_read = (0u32..0x80000, any::<u8>()).prop_map(|(offset, size)| {
+            let mut header = vec![0x01, 0x10];
+            header.extend_from_slice(&offset.to_le_bytes());
+            header.push(size);
+            header
+        });
+        let mcu_request = any::<u8>().prop_map(|byte| vec![0x11, byte]);
+        let other = any::<u8>().prop_map(|id| vec![id]);
+        (
+            prop_oneof![
+                4 => subcommand,
+                1 => report_mode,
+                1 => player_lights,
+                2 => spi_flash_read,
+                1 => mcu_request,
+                1 => other
+            ],
+            any::<[u8; 9]>(),
+            prop_oneof![
+                prop::collection::vec(any::<u8>(), 0..4),
+                prop::collection::vec(any::<u8>(), 0..50),
+            ],
+        )
+            .prop_map(|(header, timer_and_rumble, data)| {
+                let mut buf = vec![0xA2, header[0]];
+                buf.extend_from_slice(&timer_and_rumble);
+                buf.extend_from_slice(&header[1..]);
+                buf.extend(data);
+                buf
+            })
+    }
+
+    // Feeds the buffers to the protocol, which must neither panic nor fail,
+    // and writes an input report after each of them once the writer is ready.
+    fn process_reads(reports: Vec<Vec<u8>>) {
+        let replay = replay(reports);
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap()
+            .block_on(async {
+                let protocol =
+                    ControllerProtocol::new(ControllerProtocolConfig::default()).unwrap();
+                while !replay.is_finished() {
+                    protocol.process_read(&replay).await.unwrap();
+                    if *protocol.writer_ready_tx.borrow()
+                        && protocol.snapshot().report_mode.is_some()
+                    {
+                        // Waits for the next report at most, which is never
+                        // woken without a send interval.
+                        let _ = time::timeout(
+                            Duration::from_secs(1),
+                            protocol.process_write(&replay, None::<std::future::Ready<()>>),
+                        )
+                        .await
+                        .map(Result::unwrap);
+                    }
+                }
+            });
+    }
+
+    proptest! {
+        #[test]
+        fn process_arbitrary_buffers(
+            reports in prop::collection::vec(prop::collection::vec(any::<u8>(), 0..64), 1..8)
+        ) {
+            process_reads(reports);
+        }
+
+        #[test]
+        fn process_arbitrary_output_reports(
+            reports in prop::collection::vec(output_report(), 1..16)
+        ) {
+            process_reads(reports);
+        }
+    }
+}
@@ -1,4 +1,7 @@
-use super::{subcommand::Subcommand, ReportError};
+use super::{
+    subcommand::{Subcommand, SubcommandId},
+    ReportError,
+};
 use crate::controller::ControllerType;
 use bytes::BytesMut;
 use strum::Display;
@@ -86,13 +89,13 @@ impl InputReport {
 
     pub fn fill_default_report(&mut self, controller_type: ControllerType) {
         // Ref: https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/bluetooth_hid_notes.md#input-0x3f
-        self.buf[1..3].copy_from_slice(&[0x28, 0xCA, 0x08]);
+        self.buf[2..5].copy_from_slice(&[0x28, 0xCA, 0x08]);
         match controller_type {
             ControllerType::JoyConL | ControllerType::JoyConR => {
-                self.buf[4..11].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+                self.buf[5..13].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
             }
             ControllerType::ProController => {
-                self.buf[4..11].copy_from_slice(&[0x40, 0x8A, 0x4F, 0x8A, 0xD0, 0x7E, 0xDF, 0x7F]);
+                self.buf[5..13].copy_from_slice(&[0x40, 0x8A, 0x4F, 0x8A, 0xD0, 0x7E, 0xDF, 0x7F]);
             }
         }
     }
@@ -125,9 +128,9 @@ impl InputReport {
         self.buf[2] = (timer % 0x100) as u8;
     }
 
-    pub fn set_misc(&mut self) {
+    pub fn set_misc(&mut self, misc: u8) {
         // Indicates battery level + connection info
-        self.buf[3] = 0x8E;
+        self.buf[3] = misc;
     }
 
     pub fn set_button(&mut self, button_status: &[u8; 3]) {
@@ -191,11 +194,16 @@ impl InputReport {
         Ok(())
     }
 
+    pub fn set_response_subcommand_id(&mut self, id: SubcommandId) {
+        self.buf[15] = id.to_byte();
+    }
+
     pub fn sub_0x02_device_info(
         &mut self,
         mac_addr: [u8; 6],
         fm_version: Option<[u8; 2]>,
         controller_type: ControllerType,
+        use_spi_colors: bool,
     ) -> Result<(), ReportError> {
         let fm_version = fm_version.unwrap_or([0x04, 0x00]);
         self.set_response_subcommand(Subcommand::RequestDeviceInfo)?;
@@ -204,34 +212,67 @@ impl InputReport {
         self.buf[SUBCOMMAND_OFFSET + 3] = 0x02;
         self.buf[SUBCOMMAND_OFFSET + 4..SUBCOMMAND_OFFSET + 10].copy_from_slice(&mac_addr);
         self.buf[SUBCOMMAND_OFFSET + 10] = 0x01;
-        // FIXME: To figure out SPI settings to set controller colors
+        // Let the host use the colors in the SPI flash, otherwise the default ones are used.
         // https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/spi_flash_notes.md#x6000-factory-configuration-and-calibration
-        // self.buf[SUBCOMMAND_OFFSET + 11] = controller_type.device_info_color();
-        self.buf[SUBCOMMAND_OFFSET + 11] = 0x00;
+        self.buf[SUBCOMMAND_OFFSET + 11] = if use_spi_colors {
+            controller_type.device_info_color()
+        } else {
+            0x00
+        };
         Ok(())
     }
 
     pub fn sub_0x10_spi_flash_read(
         &mut self,
-        offset: u64,
+        offset: u32,
         size: u8,
         data: &[u8],
     ) -> Result<(), ReportError> {
-        // FIXME:
-        // if size > 0x1D || data.len() != size.into() {
-        //     return Err(ReportError::OutOfBounds);
-        // }
+        if size > 0x1D || data.len() != size.into() {
+            return Err(ReportError::OutOfBounds);
+        }
         // Creates input report data with spi flash read subcommand
         self.set_response_subcommand(Subcommand::SpiFlashRead)?;
-        let mut cur_offset = offset;
         // Write offset to data
-        for i in SUBCOMMAND_OFFSET..SUBCOMMAND_OFFSET + 4 {
-            self.buf[i] = (cur_offset % 0x100) as u8;
-            cur_offset = cur_offset / 0x100;
+        self.buf[SUBCOMMAND_OFFSET..SUBCOMMAND_OFFSET + 4].copy_from_slice(&offset.to_le_bytes());
+        self.buf[SUBCOMMAND_OFFSET + 4] = size;
+        self.buf[SUBCOMMAND_OFFSET + 5..SUBCOMMAND_OFFSET + 5 + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    pub fn sub_0x11_spi_flash_write(&mut self, success: bool) -> Result<(), ReportError> {
+        self.set_response_subcommand(Subcommand::SpiFlashWrite)?;
+        // 0x00 = success, 0x01 = write protected
+        self.buf[SUBCOMMAND_OFFSET] = if success { 0x00 } else { 0x01 };
+        Ok(())
+    }
+
+    pub fn sub_0x12_spi_sector_erase(&mut self, success: bool) -> Result<(), ReportError> {
+        self.set_response_subcommand(Subcommand::SpiSectorErase)?;
+        // 0x00 = success, 0x01 = write protected
+        self.buf[SUBCOMMAND_OFFSET] = if success { 0x00 } else { 0x01 };
+        Ok(())
+    }
+
+    pub fn sub_0x31_player_lights(&mut self, pattern: u8) -> Result<(), ReportError> {
+        self.set_response_subcommand(Subcommand::GetPlayerLights)?;
+        self.buf[SUBCOMMAND_OFFSET] = pattern;
+        Ok(())
+    }
+
+    pub fn sub_0x43_read_6axis_registers(
+        &mut self,
+        address: u8,
+        count: u8,
+        data: &[u8],
+    ) -> Result<(), ReportError> {
+        if count > 0x20 || data.len() != count.into() {
+            return Err(ReportError::OutOfBounds);
         }
-        // FIXME
-        self.buf[20] = data.len() as u8;
-        self.buf[21..21 + data.len()].copy_from_slice(data);
+        self.set_response_subcommand(Subcommand::Read6AxisRegisters)?;
+        self.buf[SUBCOMMAND_OFFSET] = address;
+        self.buf[SUBCOMMAND_OFFSET + 1] = count;
+        self.buf[SUBCOMMAND_OFFSET + 2..SUBCOMMAND_OFFSET + 2 + data.len()].copy_from_slice(data);
         Ok(())
     }
 
@@ -1,4 +1,8 @@
-use super::{subcommand::Subcommand, ReportError};
+use super::{
+    subcommand::{Subcommand, SubcommandId, SubcommandRequest},
+    ReportError,
+};
+use crate::controller::rumble::Rumble;
 use bytes::BytesMut;
 use strum::Display;
 
@@ -76,19 +80,36 @@ impl OutputReport {
         &self.buf[3..11]
     }
 
+    pub fn rumble(&self) -> Rumble {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.rumble_data());
+        Rumble::with_bytes(bytes)
+    }
+
     pub fn subcommand(&self) -> Result<Subcommand, ReportError> {
-        if self.buf.len() < 12 {
+        let Some(&byte) = self.buf.get(11) else {
             return Err(ReportError::SubcommandParseFailed);
-        }
-        let byte = self.buf[11];
+        };
         let Some(subcommand) = Subcommand::from_byte(byte) else {
-            return Err(ReportError::UnknownSubcommand(byte))
+            return Err(ReportError::UnknownSubcommand(byte));
         };
         Ok(subcommand)
     }
 
-    pub fn set_subcommand(&mut self, subcommand: Subcommand) {
-        self.buf[11] = subcommand.to_byte();
+    // Same as `subcommand`, but the unknown ones are kept as the raw byte.
+    pub fn subcommand_id(&self) -> Result<SubcommandId, ReportError> {
+        let Some(byte) = self.buf.get(11) else {
+            return Err(ReportError::SubcommandParseFailed);
+        };
+        Ok(SubcommandId::from_byte(*byte))
+    }
+
+    pub fn set_subcommand(&mut self, subcommand: Subcommand) -> Result<(), ReportError> {
+        let Some(byte) = self.buf.get_mut(11) else {
+            return Err(ReportError::OutOfBounds);
+        };
+        *byte = subcommand.to_byte();
+        Ok(())
     }
 
     pub fn subcommand_data(&self) -> Result<&[u8], ReportError> {
@@ -98,24 +119,50 @@ impl OutputReport {
         Ok(slice)
     }
 
-    pub fn set_subcommand_data(&mut self, data: &[u8]) {
-        self.buf[12..12 + data.len()].copy_from_slice(data);
+    // Request data of 0x11 output reports, starting from the MCU command byte.
+    pub fn mcu_request(&self) -> Result<&[u8], ReportError> {
+        let Some(slice) = self.buf.get(11..) else {
+            return Err(ReportError::NoDataAvailable);
+        };
+        Ok(slice)
+    }
+
+    pub fn set_subcommand_data(&mut self, data: &[u8]) -> Result<(), ReportError> {
+        let Some(slice) = self.buf.get_mut(12..12 + data.len()) else {
+            return Err(ReportError::OutOfBounds);
+        };
+        slice.copy_from_slice(data);
+        Ok(())
+    }
+
+    // Subcommand along with its decoded payload.
+    pub fn subcommand_request(&self) -> Result<SubcommandRequest, ReportError> {
+        SubcommandRequest::with_bytes(self.subcommand_id()?, self.subcommand_data()?)
+    }
+
+    pub fn set_subcommand_request(
+        &mut self,
+        request: &SubcommandRequest,
+    ) -> Result<(), ReportError> {
+        let Some(byte) = self.buf.get_mut(11) else {
+            return Err(ReportError::OutOfBounds);
+        };
+        *byte = request.id().to_byte();
+        self.set_subcommand_data(&request.to_bytes())
     }
 
     pub fn sub_0x10_spi_flash_read(&mut self, offset: u32, size: u8) -> Result<(), ReportError> {
-        if size > 0x1D || u32::from(size) + offset > 0x80000 {
+        if size > 0x1D
+            || offset
+                .checked_add(u32::from(size))
+                .filter(|end| *end <= 0x80000)
+                .is_none()
+        {
             return Err(ReportError::OutOfBounds);
         }
         // Creates output report data with spi flash read subcommand
         self.set_output_report_id(OutputReportId::SubCommand);
-        self.set_subcommand(Subcommand::SpiFlashRead);
-        let mut cur_offset = offset;
-        for i in 12..12 + 4 {
-            self.buf[i] = (cur_offset % 0x100) as u8;
-            cur_offset = cur_offset / 0x100;
-        }
-        self.buf[16] = size;
-        Ok(())
+        self.set_subcommand_request(&SubcommandRequest::SpiFlashRead { offset, size })
     }
 
     pub fn as_buf(&self) -> &[u8] {
@@ -134,3 +181,24 @@ impl AsMut<[u8]> for OutputReport {
         &mut self.buf
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::OutputReport;
+    use crate::controller::report::ReportError;
+
+    #[test]
+    fn spi_flash_read_out_of_bounds() {
+        let mut report = OutputReport::new();
+        assert!(report.sub_0x10_spi_flash_read(0x7FFF0, 0x10).is_ok());
+        assert!(matches!(
+            report.sub_0x10_spi_flash_read(0x7FFF1, 0x10),
+            Err(ReportError::OutOfBounds)
+        ));
+        // Does not overflow past the end of the address space.
+        assert!(matches!(
+            report.sub_0x10_spi_flash_read(u32::MAX, 0x10),
+            Err(ReportError::OutOfBounds)
+        ));
+    }
+}
@@ -1,3 +1,6 @@
+use super::ReportError;
+use crate::controller::light::{HomeLight, PlayerLights};
+use std::fmt;
 use strum::Display;
 
 // https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/bluetooth_hid_subcommands_notes.md
@@ -113,3 +116,374 @@ impl Subcommand {
         }
     }
 }
+
+// Subcommand as it's sent by the host, which may be unknown to `Subcommand`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum SubcommandId {
+    Known(Subcommand),
+    Unknown(u8),
+}
+
+impl fmt::Display for SubcommandId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Known(subcommand) => write!(f, "{subcommand}"),
+            Self::Unknown(byte) => write!(f, "Unknown({byte:#04X})"),
+        }
+    }
+}
+
+impl SubcommandId {
+    pub fn from_byte(byte: u8) -> Self {
+        match Subcommand::from_byte(byte) {
+            Some(subcommand) => Self::Known(subcommand),
+            None => Self::Unknown(byte),
+        }
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        match self {
+            Self::Known(subcommand) => subcommand.to_byte(),
+            Self::Unknown(byte) => *byte,
+        }
+    }
+}
+
+// Subcommand along with its payload decoded, as sent by the host.
+//
+// The subcommands without a typed payload are kept as the raw bytes in
+// `Other`.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum SubcommandRequest {
+    RequestDeviceInfo,
+    SetInputReportMode {
+        mode: u8,
+    },
+    TriggerButtonsElapsedTime,
+    SetShipmentState {
+        enabled: bool,
+    },
+    SpiFlashRead {
+        offset: u32,
+        size: u8,
+    },
+    // At most 0x1D bytes are written at once.
+    SpiFlashWrite {
+        offset: u32,
+        data: Vec<u8>,
+    },
+    SpiSectorErase {
+        offset: u32,
+    },
+    // Arguments are handed over to the MCU as they are.
+    SetNfcIrMcuConfig {
+        args: Vec<u8>,
+    },
+    SetNfcIrMcuState {
+        state: u8,
+    },
+    SetPlayerLights(PlayerLights),
+    GetPlayerLights,
+    SetHomeLight(HomeLight),
+    Enable6AxisSensor {
+        enabled: bool,
+    },
+    Set6AxisSensitivity {
+        gyro: u8,
+        accel: u8,
+        gyro_performance: u8,
+        accel_filter: u8,
+    },
+    WriteTo6AxisRegisters {
+        address: u8,
+        value: u8,
+    },
+    Read6AxisRegisters {
+        address: u8,
+        count: u8,
+    },
+    EnableVibration {
+        enabled: bool,
+    },
+    Other {
+        id: SubcommandId,
+        data: Vec<u8>,
+    },
+}
+
+// Max size of the data read or written by a single SPI flash subcommand.
+const SPI_FLASH_MAX_SIZE: u8 = 0x1D;
+
+impl SubcommandRequest {
+    // Decodes the payload following the subcommand byte. Trailing bytes
+    // (usually zero paddings) are ignored.
+    pub fn with_bytes(id: SubcommandId, bytes: &[u8]) -> Result<Self, ReportError> {
+        let SubcommandId::Known(subcommand) = id else {
+            return Ok(Self::Other {
+                id,
+                data: bytes.to_vec(),
+            });
+        };
+        let request = match subcommand {
+            Subcommand::RequestDeviceInfo => Self::RequestDeviceInfo,
+            Subcommand::SetInputReportMode => Self::SetInputReportMode {
+                mode: *bytes.first().ok_or(ReportError::TooShort)?,
+            },
+            Subcommand::TriggerButtonsElapsedTime => Self::TriggerButtonsElapsedTime,
+            Subcommand::SetShipmentState => Self::SetShipmentState {
+                enabled: *bytes.first().ok_or(ReportError::TooShort)? == 0x01,
+            },
+            Subcommand::SpiFlashRead => {
+                let Some(&[o0, o1, o2, o3, size]) = bytes.get(..5) else {
+                    return Err(ReportError::TooShort);
+                };
+                Self::SpiFlashRead {
+                    offset: u32::from_le_bytes([o0, o1, o2, o3]),
+                    size,
+                }
+            }
+            Subcommand::SpiFlashWrite => {
+                let Some(&[o0, o1, o2, o3, size]) = bytes.get(..5) else {
+                    return Err(ReportError::TooShort);
+                };
+                let data = match bytes.get(5..5 + usize::from(size)) {
+                    Some(data) if size <= SPI_FLASH_MAX_SIZE => data,
+                    _ => return Err(ReportError::OutOfBounds),
+                };
+                Self::SpiFlashWrite {
+                    offset: u32::from_le_bytes([o0, o1, o2, o3]),
+                    data: data.to_vec(),
+                }
+            }
+            Subcommand::SpiSectorErase => {
+                let Some(&[o0, o1, o2, o3]) = bytes.get(..4) else {
+                    return Err(ReportError::TooShort);
+                };
+                Self::SpiSectorErase {
+                    offset: u32::from_le_bytes([o0, o1, o2, o3]),
+                }
+            }
+            Subcommand::SetNfcIrMcuConfig => Self::SetNfcIrMcuConfig {
+                args: bytes.to_vec(),
+            },
+            Subcommand::SetNfcIrMcuState => Self::SetNfcIrMcuState {
+                state: *bytes.first().ok_or(ReportError::TooShort)?,
+            },
+            Subcommand::SetPlayerLights => Self::SetPlayerLights(PlayerLights::from_byte(
+                *bytes.first().ok_or(ReportError::TooShort)?,
+            )),
+            Subcommand::GetPlayerLights => Self::GetPlayerLights,
+            Subcommand::SetHomeLight => {
+                Self::SetHomeLight(HomeLight::with_bytes(bytes).ok_or(ReportError::TooShort)?)
+            }
+            Subcommand::Enable6AxisSensor => Self::Enable6AxisSensor {
+                enabled: *bytes.first().ok_or(ReportError::TooShort)? == 0x01,
+            },
+            Subcommand::Set6AxisSensitivity => {
+                let Some(&[gyro, accel, gyro_performance, accel_filter]) = bytes.get(..4) else {
+                    return Err(ReportError::TooShort);
+                };
+                Self::Set6AxisSensitivity {
+                    gyro,
+                    accel,
+                    gyro_performance,
+                    accel_filter,
+                }
+            }
+            Subcommand::WriteTo6AxisRegisters => {
+                // The second byte is always 0x01, which means "write".
+                let Some(&[address, _, value]) = bytes.get(..3) else {
+                    return Err(ReportError::TooShort);
+                };
+                Self::WriteTo6AxisRegisters { address, value }
+            }
+            Subcommand::Read6AxisRegisters => {
+                let Some(&[address, count]) = bytes.get(..2) else {
+                    return Err(ReportError::TooShort);
+                };
+                Self::Read6AxisRegisters { address, count }
+            }
+            Subcommand::EnableVibration => Self::EnableVibration {
+                enabled: *bytes.first().ok_or(ReportError::TooShort)? == 0x01,
+            },
+            _ => Self::Other {
+                id,
+                data: bytes.to_vec(),
+            },
+        };
+        Ok(request)
+    }
+
+    pub fn id(&self) -> SubcommandId {
+        let subcommand = match self {
+            Self::RequestDeviceInfo => Subcommand::RequestDeviceInfo,
+            Self::SetInputReportMode { .. } => Subcommand::SetInputReportMode,
+            Self::TriggerButtonsElapsedTime => Subcommand::TriggerButtonsElapsedTime,
+            Self::SetShipmentState { .. } => Subcommand::SetShipmentState,
+            Self::SpiFlashRead { .. } => Subcommand::SpiFlashRead,
+            Self::SpiFlashWrite { .. } => Subcommand::SpiFlashWrite,
+            Self::SpiSectorErase { .. } => Subcommand::SpiSectorErase,
+            Self::SetNfcIrMcuConfig { .. } => Subcommand::SetNfcIrMcuConfig,
+            Self::SetNfcIrMcuState { .. } => Subcommand::SetNfcIrMcuState,
+            Self::SetPlayerLights(_) => Subcommand::SetPlayerLights,
+            Self::GetPlayerLights => Subcommand::GetPlayerLights,
+            Self::SetHomeLight(_) => Subcommand::SetHomeLight,
+            Self::Enable6AxisSensor { .. } => Subcommand::Enable6AxisSensor,
+            Self::Set6AxisSensitivity { .. } => Subcommand::Set6AxisSensitivity,
+            Self::WriteTo6AxisRegisters { .. } => Subcommand::WriteTo6AxisRegisters,
+            Self::Read6AxisRegisters { .. } => Subcommand::Read6AxisRegisters,
+            Self::EnableVibration { .. } => Subcommand::EnableVibration,
+            Self::Other { id, .. } => return *id,
+        };
+        SubcommandId::Known(subcommand)
+    }
+
+    // Encodes the payload following the subcommand byte.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::RequestDeviceInfo | Self::TriggerButtonsElapsedTime | Self::GetPlayerLights => {
+                vec![]
+            }
+            Self::SetInputReportMode { mode } => vec![*mode],
+            Self::SetShipmentState { enabled }
+            | Self::Enable6AxisSensor { enabled }
+            | Self::EnableVibration { enabled } => vec![u8::from(*enabled)],
+            Self::SpiFlashRead { offset, size } => {
+                let mut buf = offset.to_le_bytes().to_vec();
+                buf.push(*size);
+                buf
+            }
+            Self::SpiFlashWrite { offset, data } => {
+                let data = &data[..data.len().min(usize::from(SPI_FLASH_MAX_SIZE))];
+                let mut buf = offset.to_le_bytes().to_vec();
+                buf.push(data.len() as u8);
+                buf.extend_from_slice(data);
+                buf
+            }
+            Self::SpiSectorErase { offset } => offset.to_le_bytes().to_vec(),
+            Self::SetNfcIrMcuConfig { args } => args.clone(),
+            Self::SetNfcIrMcuState { state } => vec![*state],
+            Self::SetPlayerLights(player_lights) => vec![player_lights.to_byte()],
+            Self::SetHomeLight(home_light) => home_light.to_bytes(),
+            Self::Set6AxisSensitivity {
+                gyro,
+                accel,
+                gyro_performance,
+                accel_filter,
+            } => vec![*gyro, *accel, *gyro_performance, *accel_filter],
+            Self::WriteTo6AxisRegisters { address, value } => vec![*address, 0x01, *value],
+            Self::Read6AxisRegisters { address, count } => vec![*address, *count],
+            Self::Other { data, .. } => data.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Subcommand, SubcommandId, SubcommandRequest};
+    use crate::controller::{
+        light::{HomeLight, HomeLightCycle, PlayerLights},
+        report::ReportError,
+    };
+
+    #[test]
+    fn parse_subcommand_id() {
+        let id = SubcommandId::from_byte(0x48);
+        assert_eq!(id, SubcommandId::Known(Subcommand::EnableVibration));
+        assert_eq!(id.to_byte(), 0x48);
+        let id = SubcommandId::from_byte(0x5A);
+        assert_eq!(id, SubcommandId::Unknown(0x5A));
+        assert_eq!(id.to_byte(), 0x5A);
+        assert_eq!(id.to_string(), "Unknown(0x5A)");
+    }
+
+    #[test]
+    fn subcommand_request_round_trip() {
+        let requests = [
+            SubcommandRequest::RequestDeviceInfo,
+            SubcommandRequest::SetInputReportMode { mode: 0x30 },
+            SubcommandRequest::SpiFlashRead {
+                offset: 0x6050,
+                size: 0x0D,
+            },
+            SubcommandRequest::SpiFlashWrite {
+                offset: 0x8010,
+                data: vec![0xB2, 0xA1],
+            },
+            SubcommandRequest::SpiSectorErase { offset: 0x1000 },
+            SubcommandRequest::SetPlayerLights(PlayerLights::from_byte(0x31)),
+            SubcommandRequest::SetHomeLight(HomeLight {
+                base_duration: 0x1,
+                start_intensity: 0xF,
+                repeat_count: 0x0,
+                cycles: vec![HomeLightCycle {
+                    intensity: 0xF,
+                    fade_duration: 0x2,
+                    duration: 0x3,
+                }],
+            }),
+            SubcommandRequest::Set6AxisSensitivity {
+                gyro: 0x03,
+                accel: 0x00,
+                gyro_performance: 0x01,
+                accel_filter: 0x02,
+            },
+            SubcommandRequest::WriteTo6AxisRegisters {
+                address: 0x20,
+                value: 0x01,
+            },
+            SubcommandRequest::EnableVibration { enabled: true },
+            SubcommandRequest::Other {
+                id: SubcommandId::from_byte(0x5A),
+                data: vec![0x01, 0x02],
+            },
+        ];
+        for request in requests {
+            let mut bytes = request.to_bytes();
+            // Paddings are ignored.
+            bytes.resize(38, 0x00);
+            let decoded = SubcommandRequest::with_bytes(request.id(), &bytes).unwrap();
+            if let SubcommandRequest::Other { .. } = request {
+                assert_eq!(decoded.to_bytes(), bytes);
+            } else {
+                assert_eq!(decoded, request);
+            }
+        }
+
+        // SPI offset is little-endian.
+        let request = SubcommandRequest::with_bytes(
+            SubcommandId::Known(Subcommand::SpiFlashRead),
+            &[0x3D, 0x60, 0x00, 0x00, 0x19],
+        )
+        .unwrap();
+        assert_eq!(
+            request,
+            SubcommandRequest::SpiFlashRead {
+                offset: 0x603D,
+                size: 0x19
+            }
+        );
+        assert!(matches!(
+            SubcommandRequest::with_bytes(SubcommandId::Known(Subcommand::SpiFlashRead), &[0x3D]),
+            Err(ReportError::TooShort)
+        ));
+        // Decoded as is, the size is checked when replying.
+        assert!(matches!(
+            SubcommandRequest::with_bytes(
+                SubcommandId::Known(Subcommand::SpiFlashRead),
+                &[0x00, 0x60, 0x00, 0x00, 0x1E]
+            ),
+            Ok(SubcommandRequest::SpiFlashRead {
+                offset: 0x6000,
+                size: 0x1E
+            })
+        ));
+        assert!(matches!(
+            SubcommandRequest::with_bytes(
+                SubcommandId::Known(Subcommand::SpiFlashWrite),
+                &[0x00, 0x60, 0x00, 0x00, 0x1E]
+            ),
+            Err(ReportError::OutOfBounds)
+        ));
+    }
+}
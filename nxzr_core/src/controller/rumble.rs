@@ -0,0 +1,200 @@
+// HD rumble data sent from the host along with output reports.
+//
+// Each side of the controller takes 4 bytes of data, which consist of the high
+// and low band of frequency and amplitude:
+//
+// Byte     0           1                   2                   3
+//          HF[7:0]     HA[6:0] | HF[8]     LA[0] | LF[6:0]     LA[8:1] + 0x40
+//
+// Ref: https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/rumble_data_table.md
+
+// Rumble data that is sent when the rumble is turned off: 320Hz / 160Hz at zero amplitude.
+pub const NEUTRAL_RUMBLE_DATA: [u8; 4] = [0x00, 0x01, 0x40, 0x40];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RumbleBand {
+    // Frequency in Hz.
+    pub frequency: f32,
+    // Amplitude in the range of [0.0, 1.0].
+    pub amplitude: f32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RumbleData {
+    pub high_band: RumbleBand,
+    pub low_band: RumbleBand,
+}
+
+impl RumbleData {
+    pub fn with_bytes(bytes: [u8; 4]) -> Self {
+        let hf_code = ((u16::from(bytes[1] & 0x01) << 8 | u16::from(bytes[0])) >> 2) as u8;
+        let ha_code = bytes[1] >> 1;
+        let lf_code = bytes[2] & 0x7F;
+        let la_code = (bytes[3].wrapping_sub(0x40) << 1) | (bytes[2] >> 7);
+        Self {
+            high_band: RumbleBand {
+                frequency: decode_frequency(hf_code, 0x60),
+                amplitude: decode_amplitude(ha_code),
+            },
+            low_band: RumbleBand {
+                frequency: decode_frequency(lf_code, 0x40),
+                amplitude: decode_amplitude(la_code),
+            },
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 4] {
+        let hf = u16::from(encode_frequency(self.high_band.frequency, 0x60)) << 2;
+        let ha = encode_amplitude(self.high_band.amplitude);
+        let lf = encode_frequency(self.low_band.frequency, 0x40);
+        let la = encode_amplitude(self.low_band.amplitude);
+        [
+            (hf & 0xFF) as u8,
+            (ha << 1) | (hf >> 8) as u8,
+            ((la & 0x01) << 7) | lf,
+            (la >> 1) + 0x40,
+        ]
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rumble {
+    pub left: RumbleData,
+    pub right: RumbleData,
+}
+
+impl Rumble {
+    pub fn with_bytes(bytes: [u8; 8]) -> Self {
+        Self {
+            left: RumbleData::with_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            right: RumbleData::with_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let mut buf = [0u8; 8];
+        buf[..4].copy_from_slice(&self.left.to_bytes());
+        buf[4..].copy_from_slice(&self.right.to_bytes());
+        buf
+    }
+}
+
+// The frequency is encoded as `round(log2(freq / 10) * 32) - base`, so that
+// each step of the code is a 1/32 octave.
+fn decode_frequency(code: u8, base: u8) -> f32 {
+    10.0 * 2f32.powf((f32::from(code) + f32::from(base)) / 32.0)
+}
+
+fn encode_frequency(frequency: f32, base: u8) -> u8 {
+    let code = ((frequency / 10.0).log2() * 32.0).round() - f32::from(base);
+    code.clamp(0.0, 127.0) as u8
+}
+
+// The amplitude is encoded on a logarithmic scale in the range of [0, 100],
+// with a different curve for the lower amplitudes.
+fn decode_amplitude(code: u8) -> f32 {
+    let code = f32::from(code.min(100));
+    if code == 0.0 {
+        0.0
+    } else if code >= 32.0 {
+        2f32.powf(code / 32.0) / 8.7
+    } else if code >= 16.0 {
+        2f32.powf(code / 16.0) / 17.0
+    } else {
+        2f32.powf((code - 27.6) / 4.0)
+    }
+}
+
+fn encode_amplitude(amplitude: f32) -> u8 {
+    // Thresholds are the decoded amplitudes of the code 32 and 16 respectively.
+    let code = if amplitude <= 0.0 {
+        0.0
+    } else if amplitude >= 2.0 / 8.7 {
+        ((amplitude * 8.7).log2() * 32.0).round()
+    } else if amplitude >= 2.0 / 17.0 {
+        ((amplitude * 17.0).log2() * 16.0).round()
+    } else {
+        (amplitude.log2() * 4.0 + 27.6).round()
+    };
+    code.clamp(0.0, 100.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rumble, RumbleData, NEUTRAL_RUMBLE_DATA};
+
+    fn assert_approx(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() <= expected * 0.01,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn decode_neutral_rumble() {
+        let data = RumbleData::with_bytes(NEUTRAL_RUMBLE_DATA);
+        assert_approx(data.high_band.frequency, 320.0);
+        assert_approx(data.low_band.frequency, 160.0);
+        assert_eq!(data.high_band.amplitude, 0.0);
+        assert_eq!(data.low_band.amplitude, 0.0);
+        assert_eq!(data.to_bytes(), NEUTRAL_RUMBLE_DATA);
+    }
+
+    #[test]
+    fn decode_frequency_table() {
+        // (byte 0, byte 1, frequency) pairs of the high band.
+        let hf_cases = [
+            (0x00, 0x00, 80.0),
+            (0xFC, 0x01, 1252.57),
+            (0x00, 0x01, 320.0),
+        ];
+        for (b0, b1, frequency) in hf_cases {
+            let data = RumbleData::with_bytes([b0, b1, 0x40, 0x40]);
+            assert_approx(data.high_band.frequency, frequency);
+        }
+        // (byte 2, frequency) pairs of the low band.
+        let lf_cases = [(0x01, 40.87), (0x7F, 626.28), (0x40, 160.0)];
+        for (b2, frequency) in lf_cases {
+            let data = RumbleData::with_bytes([0x00, 0x01, b2, 0x40]);
+            assert_approx(data.low_band.frequency, frequency);
+        }
+    }
+
+    #[test]
+    fn decode_amplitude_table() {
+        // (high band amplitude byte, low band amplitude bytes, amplitude)
+        let cases = [
+            (0x02, [0x80, 0x40], 0.0100),
+            (0x20, [0x00, 0x48], 0.1176),
+            (0x22, [0x80, 0x48], 0.1230),
+            (0x40, [0x00, 0x50], 0.2299),
+            (0xC8, [0x00, 0x72], 1.0),
+        ];
+        for (ha, [b2, b3], amplitude) in cases {
+            let data = RumbleData::with_bytes([0x00, ha, b2, b3]);
+            assert_approx(data.high_band.amplitude, amplitude);
+            assert_approx(data.low_band.amplitude, amplitude);
+        }
+    }
+
+    #[test]
+    fn amplitude_round_trip() {
+        for code in 0..=100u8 {
+            let bytes = [
+                0x00,
+                (code << 1) | 0x01,
+                ((code & 0x01) << 7) | 0x40,
+                (code >> 1) + 0x40,
+            ];
+            assert_eq!(RumbleData::with_bytes(bytes).to_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn rumble_round_trip() {
+        let bytes = [0x28, 0x88, 0x60, 0x61, 0x00, 0x01, 0x40, 0x40];
+        let rumble = Rumble::with_bytes(bytes);
+        assert_eq!(rumble.to_bytes(), bytes);
+        assert_eq!(rumble.right, RumbleData::with_bytes(NEUTRAL_RUMBLE_DATA));
+    }
+}
@@ -0,0 +1,262 @@
+use super::state::{button::ButtonKey, ControllerState, StateError};
+use std::{collections::VecDeque, fmt};
+use tokio::{
+    sync::oneshot,
+    time::{Duration, Instant},
+};
+
+type Change = Box<dyn FnOnce(&mut ControllerState) -> Result<(), StateError> + Send>;
+
+// How long a change is kept before the next one is applied.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Hold {
+    // Number of the input reports sent with the change, `0` lets the next
+    // change be applied to the same report.
+    Reports(u32),
+    // Keeps the change until the duration has passed since the first report
+    // sent with it, which is rounded up to the send interval.
+    Duration(Duration),
+}
+
+struct Step {
+    change: Change,
+    hold: Hold,
+}
+
+// Timeline of the controller state changes applied by the writer, e.g. to
+// press a button for exactly 3 input reports.
+//
+// The sequence advances along with the input reports sent in the standard
+// full mode, so it does not advance while the protocol is paused or no report
+// mode is set.
+#[derive(Default)]
+pub struct InputSequence {
+    steps: VecDeque<Step>,
+}
+
+impl InputSequence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Applies the change, and keeps it for the hold before the next one.
+    pub fn then(
+        mut self,
+        change: impl FnOnce(&mut ControllerState) -> Result<(), StateError> + Send + 'static,
+        hold: Hold,
+    ) -> Self {
+        self.steps.push_back(Step {
+            change: Box::new(change),
+            hold,
+        });
+        self
+    }
+
+    pub fn set_button(self, key: ButtonKey, flag: bool, hold: Hold) -> Self {
+        self.then(
+            move |state| state.button_state_mut().set_button(key, flag),
+            hold,
+        )
+    }
+
+    // Presses the button for the hold, then releases it for a report so that
+    // the release is sent before the next change.
+    pub fn press(self, key: ButtonKey, hold: Hold) -> Self {
+        self.set_button(key, true, hold)
+            .set_button(key, false, Hold::Reports(1))
+    }
+
+    // Keeps the current state for the hold.
+    pub fn wait(self, hold: Hold) -> Self {
+        self.then(|_| Ok(()), hold)
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+impl fmt::Debug for InputSequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InputSequence")
+            .field(
+                "holds",
+                &self.steps.iter().map(|step| step.hold).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+struct HoldState {
+    hold: Hold,
+    started_at: Instant,
+    // Number of the input reports sent since the change is applied.
+    written: u32,
+}
+
+impl HoldState {
+    fn is_over(&self, now: Instant) -> bool {
+        match self.hold {
+            Hold::Reports(count) => self.written >= count,
+            Hold::Duration(duration) => now.saturating_duration_since(self.started_at) >= duration,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ActiveSequence {
+    sequence: InputSequence,
+    hold: Option<HoldState>,
+    done_tx: oneshot::Sender<Result<(), StateError>>,
+}
+
+// Runs the queued sequences one by one in the writer.
+#[derive(Debug, Default)]
+pub(crate) struct Sequencer {
+    queue: VecDeque<ActiveSequence>,
+}
+
+impl Sequencer {
+    // Queues the sequence, which is resolved once the last change has been
+    // sent for its hold.
+    pub fn push(&mut self, sequence: InputSequence) -> oneshot::Receiver<Result<(), StateError>> {
+        let (done_tx, done_rx) = oneshot::channel();
+        if sequence.is_empty() {
+            let _ = done_tx.send(Ok(()));
+        } else {
+            self.queue.push_back(ActiveSequence {
+                sequence,
+                hold: None,
+                done_tx,
+            });
+        }
+        done_rx
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.queue.is_empty()
+    }
+
+    // Applies the changes due for the input report about to be generated.
+    pub fn advance(&mut self, now: Instant, state: &mut ControllerState) {
+        while let Some(active) = self.queue.front_mut() {
+            if matches!(&active.hold, Some(hold) if !hold.is_over(now)) {
+                return;
+            }
+            let Some(step) = active.sequence.steps.pop_front() else {
+                // The last change is yet to be sent if it has just been
+                // applied, which is resolved after the write instead.
+                if matches!(&active.hold, Some(hold) if hold.written == 0) {
+                    return;
+                }
+                self.resolve(Ok(()));
+                continue;
+            };
+            if let Err(err) = (step.change)(state) {
+                self.resolve(Err(err));
+                continue;
+            }
+            active.hold = Some(HoldState {
+                hold: step.hold,
+                started_at: now,
+                written: 0,
+            });
+        }
+    }
+
+    // Counts the input report sent, and resolves the sequence if it's over
+    // without waiting for the next report.
+    pub fn complete(&mut self, now: Instant) {
+        let Some(active) = self.queue.front_mut() else {
+            return;
+        };
+        let Some(hold) = &mut active.hold else {
+            return;
+        };
+        hold.written = hold.written.saturating_add(1);
+        if active.sequence.is_empty() && hold.is_over(now) {
+            self.resolve(Ok(()));
+        }
+    }
+
+    fn resolve(&mut self, result: Result<(), StateError>) {
+        if let Some(active) = self.queue.pop_front() {
+            let _ = active.done_tx.send(result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Hold, InputSequence, Sequencer};
+    use crate::controller::state::{button::ButtonKey, ControllerState};
+    use tokio::{
+        sync::oneshot::error::TryRecvError,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn advance_sequence() {
+        let mut sequencer = Sequencer::default();
+        let mut state = ControllerState::new();
+        let mut done_rx = sequencer.push(
+            InputSequence::new()
+                .press(ButtonKey::A, Hold::Reports(3))
+                .wait(Hold::Reports(0))
+                .press(ButtonKey::B, Hold::Duration(Duration::from_millis(20))),
+        );
+        assert!(sequencer.is_active());
+        let started_at = Instant::now();
+        let mut reports = vec![];
+        for i in 0..10 {
+            let now = started_at + Duration::from_millis(i * 8);
+            sequencer.advance(now, &mut state);
+            let buttons = state.button_state();
+            reports.push((
+                buttons.is_button_set(ButtonKey::A),
+                buttons.is_button_set(ButtonKey::B),
+            ));
+            sequencer.complete(now);
+            if !sequencer.is_active() {
+                break;
+            }
+            assert!(matches!(done_rx.try_recv(), Err(TryRecvError::Empty)));
+        }
+        assert_eq!(
+            reports,
+            [
+                (true, false),
+                (true, false),
+                (true, false),
+                (false, false),
+                (false, true),
+                (false, true),
+                (false, true),
+                (false, false),
+            ]
+        );
+        assert!(matches!(done_rx.try_recv(), Ok(Ok(()))));
+
+        // Resolved with an error if the change fails.
+        let mut done_rx =
+            sequencer.push(InputSequence::new().press(ButtonKey::Sr, Hold::Reports(1)));
+        sequencer.advance(started_at, &mut state);
+        assert!(matches!(done_rx.try_recv(), Ok(Err(_))));
+        assert!(!sequencer.is_active());
+
+        // The last change without a hold is resolved only after it's sent.
+        let mut done_rx =
+            sequencer.push(InputSequence::new().set_button(ButtonKey::A, true, Hold::Reports(0)));
+        sequencer.advance(started_at, &mut state);
+        assert!(state.button_state().is_button_set(ButtonKey::A));
+        assert!(matches!(done_rx.try_recv(), Err(TryRecvError::Empty)));
+        sequencer.complete(started_at);
+        assert!(matches!(done_rx.try_recv(), Ok(Ok(()))));
+        assert!(!sequencer.is_active());
+    }
+}
@@ -1,7 +1,52 @@
-use std::ops::{Deref, DerefMut};
+use std::{
+    fmt,
+    io::Write,
+    ops::{Deref, DerefMut, Range},
+    path::Path,
+};
 
+use super::color::ControllerColors;
 use bytes::BytesMut;
 
+// Size of the flash memory of the genuine controllers (512KB).
+pub const SPI_FLASH_SIZE: usize = 0x80000;
+
+// Size of a single sector which is the unit of erase operations.
+pub const SECTOR_SIZE: u32 = 0x1000;
+
+// Regions that the host is not allowed to modify: the bootloader, pairing info,
+// factory configuration and calibration, and the firmware images.
+//
+// Ref: https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/spi_flash_notes.md
+const READ_ONLY_REGIONS: [Range<u32>; 2] = [0x00000..0x08000, 0x10000..0x80000];
+
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum SpiFlashError {
+    // Returned when accessing a range that exceeds the size of the flash.
+    #[error("out of bounds; invalid range")]
+    OutOfBounds,
+    // Returned when attempting to modify a read-only region.
+    #[error("the region is write protected")]
+    WriteProtected,
+    // Returned when the supplied image does not match with the flash size.
+    #[error("invalid image size: {0} bytes, expected {SPI_FLASH_SIZE} bytes")]
+    InvalidImageSize(usize),
+    #[error("io error: {message}")]
+    Io {
+        kind: std::io::ErrorKind,
+        message: String,
+    },
+}
+
+impl From<std::io::Error> for SpiFlashError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io {
+            kind: err.kind(),
+            message: err.to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct SpiFlashConfig {
     pub buffer: Option<BytesMut>,
@@ -9,11 +54,20 @@ pub struct SpiFlashConfig {
     pub reset: bool,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct SpiFlash {
     buf: BytesMut,
 }
 
+// Prints the size only, since dumping the entire image is not really helpful.
+impl fmt::Debug for SpiFlash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpiFlash")
+            .field("size", &self.buf.len())
+            .finish_non_exhaustive()
+    }
+}
+
 impl Default for SpiFlash {
     fn default() -> Self {
         Self::new()
@@ -27,8 +81,8 @@ impl SpiFlash {
 
     pub fn with_config(config: SpiFlashConfig) -> Option<Self> {
         let size = match config.size {
-            Some(size) => std::cmp::max(size, 0x80000),
-            None => 0x80000,
+            Some(size) => std::cmp::max(size, SPI_FLASH_SIZE),
+            None => SPI_FLASH_SIZE,
         };
         let mut should_reset = false;
         let mut buf = match config.buffer {
@@ -49,16 +103,110 @@ impl SpiFlash {
             }
         };
         if should_reset {
-            // L-stick factory calibration
-            buf[0x603D..0x6046]
-                .copy_from_slice(&[0x00, 0x07, 0x70, 0x00, 0x08, 0x80, 0x00, 0x07, 0x70]);
-            // R-stick factory calibration
-            buf[0x6046..0x604F]
-                .copy_from_slice(&[0x00, 0x08, 0x80, 0x00, 0x07, 0x70, 0x00, 0x07, 0x70]);
+            reset_to_factory(&mut buf);
         }
         Some(Self { buf })
     }
 
+    // Loads a raw image (e.g. a dump of the genuine controller) from the file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SpiFlashError> {
+        let data = std::fs::read(path)?;
+        let len = data.len();
+        Self::with_config(SpiFlashConfig {
+            buffer: Some(BytesMut::from(&data[..])),
+            ..Default::default()
+        })
+        .ok_or(SpiFlashError::InvalidImageSize(len))
+    }
+
+    // Saves the raw image to the file, replacing the existing one. The image
+    // is written to a temporary file next to it first, so that a crash never
+    // leaves a truncated image behind.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SpiFlashError> {
+        let path = path.as_ref();
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(&self.buf)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    // Returns a slice of the flash memory for the given range, or `None` when
+    // the range exceeds the size of the flash.
+    pub fn read(&self, offset: u32, size: u8) -> Option<&[u8]> {
+        let start = offset as usize;
+        let end = start.checked_add(size as usize)?;
+        self.buf.get(start..end)
+    }
+
+    // Overwrites the flash memory at the given offset with the data.
+    pub fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), SpiFlashError> {
+        let range = self.writable_range(offset, data.len())?;
+        self.buf[range].copy_from_slice(data);
+        Ok(())
+    }
+
+    // Erases the sector that contains the given offset by filling it with 0xFF.
+    pub fn erase_sector(&mut self, offset: u32) -> Result<(), SpiFlashError> {
+        let start = offset - offset % SECTOR_SIZE;
+        let range = self.writable_range(start, SECTOR_SIZE as usize)?;
+        self.buf[range].fill(0xFF);
+        Ok(())
+    }
+
+    pub fn is_write_protected(offset: u32, size: usize) -> bool {
+        let start = offset as u64;
+        let end = start + size as u64;
+        READ_ONLY_REGIONS
+            .iter()
+            .any(|region| start < region.end as u64 && (region.start as u64) < end)
+    }
+
+    fn writable_range(&self, offset: u32, size: usize) -> Result<Range<usize>, SpiFlashError> {
+        let start = offset as usize;
+        let Some(end) = start.checked_add(size).filter(|&end| end <= self.buf.len()) else {
+            return Err(SpiFlashError::OutOfBounds);
+        };
+        if Self::is_write_protected(offset, size) {
+            return Err(SpiFlashError::WriteProtected);
+        }
+        Ok(start..end)
+    }
+
+    // Returns the controller colors if the image has color info.
+    pub fn colors(&self) -> Option<ControllerColors> {
+        match self.buf[0x601B] {
+            0x01 => ControllerColors::with_bytes(&self.buf[0x6050..0x605C], false),
+            0x02 => ControllerColors::with_bytes(&self.buf[0x6050..0x605C], true),
+            _ => None,
+        }
+    }
+
+    // Writes the controller colors and marks the image to have color info.
+    //
+    // Please note that, this bypasses the write protection as colors reside
+    // in the factory configuration region.
+    pub fn set_colors(&mut self, colors: &ControllerColors) {
+        self.buf[0x601B] = if colors.has_grip_colors() { 0x02 } else { 0x01 };
+        self.buf[0x6050..0x605C].copy_from_slice(&colors.to_bytes());
+    }
+
+    // Returns the raw color info, i.e. the flag at 0x601B followed by the
+    // colors at 0x6050, to restore the image after `set_colors`.
+    pub fn raw_colors(&self) -> [u8; 13] {
+        let mut raw = [0; 13];
+        raw[0] = self.buf[0x601B];
+        raw[1..].copy_from_slice(&self.buf[0x6050..0x605C]);
+        raw
+    }
+
+    pub fn set_raw_colors(&mut self, raw: &[u8; 13]) {
+        self.buf[0x601B] = raw[0];
+        self.buf[0x6050..0x605C].copy_from_slice(&raw[1..]);
+    }
+
     pub fn factory_l_stick_calibration(&self) -> &[u8] {
         &self.buf[0x603D..0x6046]
     }
@@ -98,6 +246,38 @@ impl SpiFlash {
     }
 }
 
+// Populates the regions that the host reads during the initial handshake.
+//
+// Ref: https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/spi_flash_notes.md
+fn reset_to_factory(buf: &mut [u8]) {
+    // Serial number (all 0xFF means no serial number)
+    buf[0x6000..0x6010].fill(0xFF);
+    // L-stick factory calibration
+    buf[0x603D..0x6046].copy_from_slice(&[0xBA, 0x15, 0x62, 0x11, 0xB8, 0x7F, 0x29, 0x06, 0x5B]);
+    // R-stick factory calibration
+    buf[0x6046..0x604F].copy_from_slice(&[0xFF, 0xE7, 0x7E, 0x0E, 0x36, 0x56, 0x9E, 0x85, 0x60]);
+    // Body color, buttons color (left/right grip colors are left unset)
+    buf[0x6050..0x6056].copy_from_slice(&[0xBC, 0x11, 0x42, 0x75, 0xA9, 0x28]);
+    buf[0x6056..0x605C].fill(0xFF);
+    // 6-axis horizontal offsets
+    buf[0x6080..0x6086].copy_from_slice(&[0x50, 0xFD, 0x00, 0x00, 0xC6, 0x0F]);
+    // L-stick/R-stick parameters
+    const STICK_PARAMS: [u8; 18] = [
+        0x0F, 0x30, 0x61, 0x96, 0x30, 0xF3, 0xD4, 0x14, 0x54, 0x41, 0x15, 0x54, 0xC7, 0x77, 0x99,
+        0xC3, 0x33, 0x66,
+    ];
+    buf[0x6086..0x6098].copy_from_slice(&STICK_PARAMS);
+    buf[0x6098..0x60AA].copy_from_slice(&STICK_PARAMS);
+    // No user stick calibration
+    buf[0x8010..0x8026].fill(0xFF);
+    // User 6-axis calibration: magic bytes followed by the calibration data
+    buf[0x8026..0x8028].copy_from_slice(&[0xB2, 0xA1]);
+    buf[0x8028..0x8040].copy_from_slice(&[
+        0xBE, 0xFF, 0x3E, 0x00, 0xF0, 0x01, 0x00, 0x40, 0x00, 0x40, 0x00, 0x40, 0xFE, 0xFF, 0xFF,
+        0xFF, 0x08, 0x00, 0xE7, 0x3B, 0xE7, 0x3B, 0xE7, 0x3B,
+    ]);
+}
+
 impl Deref for SpiFlash {
     type Target = [u8];
 
@@ -123,3 +303,169 @@ impl AsMut<[u8]> for SpiFlash {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{SpiFlash, SpiFlashError};
+    use crate::controller::color::{ControllerColors, Rgb};
+
+    // Offsets and sizes requested by the Switch during the initial handshake.
+    #[test]
+    fn read_handshake_offsets() {
+        let spi_flash = SpiFlash::new();
+        let cases: &[(u32, u8, &[u8])] = &[
+            (0x6000, 0x10, &[0xFF; 16]),
+            (
+                0x6050,
+                0x0D,
+                &[
+                    0xBC, 0x11, 0x42, 0x75, 0xA9, 0x28, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+                ],
+            ),
+            (
+                0x6080,
+                0x18,
+                &[
+                    0x50, 0xFD, 0x00, 0x00, 0xC6, 0x0F, 0x0F, 0x30, 0x61, 0x96, 0x30, 0xF3, 0xD4,
+                    0x14, 0x54, 0x41, 0x15, 0x54, 0xC7, 0x77, 0x99, 0xC3, 0x33, 0x66,
+                ],
+            ),
+            (
+                0x6098,
+                0x12,
+                &[
+                    0x0F, 0x30, 0x61, 0x96, 0x30, 0xF3, 0xD4, 0x14, 0x54, 0x41, 0x15, 0x54, 0xC7,
+                    0x77, 0x99, 0xC3, 0x33, 0x66,
+                ],
+            ),
+            (
+                0x603D,
+                0x19,
+                &[
+                    0xBA, 0x15, 0x62, 0x11, 0xB8, 0x7F, 0x29, 0x06, 0x5B, 0xFF, 0xE7, 0x7E, 0x0E,
+                    0x36, 0x56, 0x9E, 0x85, 0x60, 0xFF, 0xBC, 0x11, 0x42, 0x75, 0xA9, 0x28,
+                ],
+            ),
+            (
+                0x8010,
+                0x18,
+                &[
+                    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+                    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xB2, 0xA1,
+                ],
+            ),
+            (
+                0x8028,
+                0x18,
+                &[
+                    0xBE, 0xFF, 0x3E, 0x00, 0xF0, 0x01, 0x00, 0x40, 0x00, 0x40, 0x00, 0x40, 0xFE,
+                    0xFF, 0xFF, 0xFF, 0x08, 0x00, 0xE7, 0x3B, 0xE7, 0x3B, 0xE7, 0x3B,
+                ],
+            ),
+        ];
+        for &(offset, size, expected) in cases {
+            assert_eq!(
+                spi_flash.read(offset, size).unwrap(),
+                expected,
+                "offset: {offset:#06X}"
+            );
+        }
+    }
+
+    #[test]
+    fn read_out_of_bounds() {
+        let spi_flash = SpiFlash::new();
+        assert!(spi_flash.read(0x7FFF0, 0x10).is_some());
+        assert!(spi_flash.read(0x7FFF1, 0x10).is_none());
+        assert!(spi_flash.read(u32::MAX, 0x01).is_none());
+    }
+
+    #[test]
+    fn save_and_load_image() {
+        let path = std::env::temp_dir().join(format!("nxzr_spi_flash_{}.bin", std::process::id()));
+        let mut spi_flash = SpiFlash::new();
+        spi_flash.write(0x8010, &[0xB2, 0xA1]).unwrap();
+        spi_flash.save(&path).unwrap();
+        let loaded = SpiFlash::load(&path).unwrap();
+        assert_eq!(&loaded[..], &spi_flash[..]);
+        // Replaces the existing image without leaving the temporary file.
+        spi_flash.write(0x8010, &[0xB2, 0xA2]).unwrap();
+        spi_flash.save(&path).unwrap();
+        assert_eq!(&SpiFlash::load(&path).unwrap()[..], &spi_flash[..]);
+        assert!(!path.with_extension("bin.tmp").exists());
+        std::fs::write(&path, [0xFF; 0x1000]).unwrap();
+        assert!(matches!(
+            SpiFlash::load(&path),
+            Err(SpiFlashError::InvalidImageSize(0x1000))
+        ));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            SpiFlash::load(&path),
+            Err(SpiFlashError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn set_colors() {
+        let mut spi_flash = SpiFlash::new();
+        assert!(spi_flash.colors().is_none());
+        let colors = ControllerColors::new(Rgb::from_u32(0x323232), Rgb::from_u32(0xFFFFFF));
+        spi_flash.set_colors(&colors);
+        assert_eq!(spi_flash.colors(), Some(colors));
+        assert_eq!(
+            spi_flash.read(0x6050, 0x0D).unwrap(),
+            &[0x32, 0x32, 0x32, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+    }
+
+    #[test]
+    fn restore_raw_colors() {
+        let mut spi_flash = SpiFlash::new();
+        let raw_colors = spi_flash.raw_colors();
+        let colors = ControllerColors::new(Rgb::from_u32(0x323232), Rgb::from_u32(0xFFFFFF));
+        spi_flash.set_colors(&colors);
+        assert_ne!(spi_flash.raw_colors(), raw_colors);
+        spi_flash.set_raw_colors(&raw_colors);
+        assert_eq!(&spi_flash[..], &SpiFlash::new()[..]);
+    }
+
+    #[test]
+    fn write_user_calibration() {
+        let mut spi_flash = SpiFlash::new();
+        let l_cal = [
+            0xB2, 0xA1, 0x00, 0x07, 0x70, 0x00, 0x08, 0x80, 0x00, 0x07, 0x70,
+        ];
+        spi_flash.write(0x8010, &l_cal).unwrap();
+        assert_eq!(spi_flash.read(0x8010, 0x0B).unwrap(), &l_cal);
+        assert_eq!(spi_flash.user_l_stick_calibration().unwrap(), &l_cal[2..]);
+        spi_flash.erase_sector(0x8010).unwrap();
+        assert!(spi_flash
+            .read(0x8000, 0x1D)
+            .unwrap()
+            .iter()
+            .all(|&b| b == 0xFF));
+        assert!(spi_flash.user_l_stick_calibration().is_none());
+    }
+
+    #[test]
+    fn write_protected_regions() {
+        let mut spi_flash = SpiFlash::new();
+        assert!(matches!(
+            spi_flash.write(0x603D, &[0x00; 9]),
+            Err(SpiFlashError::WriteProtected)
+        ));
+        assert!(matches!(
+            spi_flash.write(0x7FFF, &[0x00; 2]),
+            Err(SpiFlashError::WriteProtected)
+        ));
+        assert!(matches!(
+            spi_flash.erase_sector(0x6000),
+            Err(SpiFlashError::WriteProtected)
+        ));
+        assert!(matches!(
+            spi_flash.write(0x7FFFF, &[0x00; 2]),
+            Err(SpiFlashError::OutOfBounds)
+        ));
+        assert_eq!(spi_flash.read(0x603D, 0x01).unwrap(), &[0xBA]);
+    }
+}
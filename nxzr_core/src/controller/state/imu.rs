@@ -1,104 +1,629 @@
+use super::StateError;
+use tokio::time::{Duration, Instant};
+
 // Source: https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/imu_sensor_notes.md#:~:text=gyro_vector_component%20%3D%20gyro_raw_component%20*%200.070f%20(%3D4588/65535)
 // The equation is: gyro_vector_component = gyro_raw_component * G_GAIN / SENSOR_RES
 // Where `SENSOR_RES` is 16bit, thus `65535`, `G_GAIN` is the degrees per second sensitivity range.
-const GYROSCOPE_COEFF: f32 = 0.07;
-const GYROSCOPE_SENSITIVITY_MULTIPLIER: f32 = 57.3;
+// The gyroscope has a ~15% headroom over the nominal range, e.g. `G_GAIN` is `4588` for ±2000dps.
+const SENSOR_RES: f32 = 65535.0;
+const GYROSCOPE_GAIN_HEADROOM: f32 = 4588.0 / 4000.0;
+
+// Standard gravity in g, which is applied to the Z axis when the controller is
+// lying flat on a table.
+pub const GRAVITY: f32 = 1.0;
+
+// The 6-axis data is sampled 3 times per report, with 5ms interval.
+const SAMPLES_PER_REPORT: u32 = 3;
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(5);
+// Bounds of the time over which the motion towards a new orientation is
+// spread, so that neither a burst nor a stall of the updates yields absurd
+// angular velocities.
+const MIN_MOTION_DURATION: Duration = SAMPLE_INTERVAL;
+const MAX_MOTION_DURATION: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct Vector3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Vector3 {
+    pub const fn new(x: f32, y: f32, z: f32) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn scale(&self, s: f32) -> Self {
+        Self::new(self.x * s, self.y * s, self.z * s)
+    }
+
+    pub fn add(&self, v: &Vector3) -> Self {
+        Self::new(self.x + v.x, self.y + v.y, self.z + v.z)
+    }
+
+    pub fn lerp(&self, v: &Vector3, t: f32) -> Self {
+        self.add(&v.add(&self.scale(-1.0)).scale(t))
+    }
+}
+
+// Unit quaternion representing the rotation from the controller body frame to
+// the world frame, where the world Z axis points up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Default for Quaternion {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
 
-// FIXME: update to 3000 as a default
-// Minimum value must be greater than 0.
-const DEFAULT_SENSITIVITY: i32 = 8000;
+impl Quaternion {
+    pub const IDENTITY: Self = Self::new(1.0, 0.0, 0.0, 0.0);
+
+    pub const fn new(w: f32, x: f32, y: f32, z: f32) -> Self {
+        Self { w, x, y, z }
+    }
+
+    // Creates from the Euler angles in radians, applied in yaw (Z), pitch (Y)
+    // and roll (X) order.
+    pub fn from_euler(roll: f32, pitch: f32, yaw: f32) -> Self {
+        let (sr, cr) = (roll / 2.0).sin_cos();
+        let (sp, cp) = (pitch / 2.0).sin_cos();
+        let (sy, cy) = (yaw / 2.0).sin_cos();
+        Self::new(
+            cr * cp * cy + sr * sp * sy,
+            sr * cp * cy - cr * sp * sy,
+            cr * sp * cy + sr * cp * sy,
+            cr * cp * sy - sr * sp * cy,
+        )
+    }
+
+    pub fn conjugate(&self) -> Self {
+        Self::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    pub fn dot(&self, q: &Quaternion) -> f32 {
+        self.w * q.w + self.x * q.x + self.y * q.y + self.z * q.z
+    }
+
+    pub fn mul(&self, q: &Quaternion) -> Self {
+        Self::new(
+            self.w * q.w - self.x * q.x - self.y * q.y - self.z * q.z,
+            self.w * q.x + self.x * q.w + self.y * q.z - self.z * q.y,
+            self.w * q.y - self.x * q.z + self.y * q.w + self.z * q.x,
+            self.w * q.z + self.x * q.y - self.y * q.x + self.z * q.w,
+        )
+    }
+
+    // Returns `None` if the quaternion can't be normalized, e.g. all zeros.
+    pub fn normalize(&self) -> Option<Self> {
+        let norm = self.dot(self).sqrt();
+        if !norm.is_normal() {
+            return None;
+        }
+        Some(Self::new(
+            self.w / norm,
+            self.x / norm,
+            self.y / norm,
+            self.z / norm,
+        ))
+    }
+
+    // Rotates the vector by this quaternion.
+    pub fn rotate(&self, v: &Vector3) -> Vector3 {
+        let p = self
+            .mul(&Quaternion::new(0.0, v.x, v.y, v.z))
+            .mul(&self.conjugate());
+        Vector3::new(p.x, p.y, p.z)
+    }
+
+    // Rotation vector (axis multiplied by angle in radians) of this quaternion,
+    // taking the shortest path.
+    pub fn to_rotation_vector(&self) -> Vector3 {
+        let q = if self.w < 0.0 {
+            self.scale(-1.0)
+        } else {
+            *self
+        };
+        let sin_half = (q.x * q.x + q.y * q.y + q.z * q.z).sqrt();
+        if sin_half <= f32::EPSILON {
+            // Small angle approximation.
+            return Vector3::new(q.x * 2.0, q.y * 2.0, q.z * 2.0);
+        }
+        let angle = 2.0 * sin_half.atan2(q.w);
+        Vector3::new(q.x, q.y, q.z).scale(angle / sin_half)
+    }
+
+    // Spherical linear interpolation, taking the shortest path.
+    pub fn slerp(&self, q: &Quaternion, t: f32) -> Self {
+        let mut dot = self.dot(q);
+        let q = if dot < 0.0 {
+            dot = -dot;
+            q.scale(-1.0)
+        } else {
+            *q
+        };
+        let (s0, s1) = if dot > 0.9995 {
+            // Falls back to linear interpolation for close quaternions.
+            (1.0 - t, t)
+        } else {
+            let theta = dot.acos();
+            let sin_theta = theta.sin();
+            (
+                ((1.0 - t) * theta).sin() / sin_theta,
+                (t * theta).sin() / sin_theta,
+            )
+        };
+        let r = Self::new(
+            self.w * s0 + q.w * s1,
+            self.x * s0 + q.x * s1,
+            self.y * s0 + q.y * s1,
+            self.z * s0 + q.z * s1,
+        );
+        r.normalize().unwrap_or(r)
+    }
+
+    fn scale(&self, s: f32) -> Self {
+        Self::new(self.w * s, self.x * s, self.y * s, self.z * s)
+    }
+}
+
+// Motion towards the last orientation set, tracked to synthesize the 6-axis
+// samples at any point in time.
+#[derive(Clone, Copy, Debug)]
+struct OrientationTarget {
+    // Orientation at the time the target is set, which the motion starts from.
+    origin: Quaternion,
+    target: Quaternion,
+    origin_linear_accel: Vector3,
+    linear_accel: Vector3,
+    set_at: Instant,
+    // Time since the previous orientation, over which the motion is spread.
+    duration: Duration,
+    // Angular velocity in the body frame during the motion, and the one of the
+    // previous motion for samples taken before the target is set.
+    gyro: Vector3,
+    prev_gyro: Vector3,
+}
+
+impl OrientationTarget {
+    fn new(orientation: Quaternion, linear_accel: Vector3, now: Instant) -> Self {
+        Self {
+            origin: orientation,
+            target: orientation,
+            origin_linear_accel: linear_accel,
+            linear_accel,
+            set_at: now,
+            duration: MIN_MOTION_DURATION,
+            gyro: Vector3::default(),
+            prev_gyro: Vector3::default(),
+        }
+    }
+
+    // Starts the motion towards the new target from where it is at the time.
+    fn retarget(&self, orientation: Quaternion, linear_accel: Vector3, now: Instant) -> Self {
+        let (origin, origin_linear_accel) = self.pose_at(now);
+        let duration = now
+            .saturating_duration_since(self.set_at)
+            .clamp(MIN_MOTION_DURATION, MAX_MOTION_DURATION);
+        let gyro = origin
+            .conjugate()
+            .mul(&orientation)
+            .to_rotation_vector()
+            .scale(f32::to_degrees(1.0) / duration.as_secs_f32());
+        Self {
+            origin,
+            target: orientation,
+            origin_linear_accel,
+            linear_accel,
+            set_at: now,
+            duration,
+            gyro,
+            prev_gyro: self.gyro_at(now),
+        }
+    }
+
+    // Orientation and linear acceleration at the time.
+    fn pose_at(&self, at: Instant) -> (Quaternion, Vector3) {
+        let t = (at.saturating_duration_since(self.set_at).as_secs_f32()
+            / self.duration.as_secs_f32())
+        .min(1.0);
+        (
+            self.origin.slerp(&self.target, t),
+            self.origin_linear_accel.lerp(&self.linear_accel, t),
+        )
+    }
+
+    fn gyro_at(&self, at: Instant) -> Vector3 {
+        if at < self.set_at {
+            self.prev_gyro
+        } else if at <= self.set_at + self.duration {
+            self.gyro
+        } else {
+            // Holding the target.
+            Vector3::default()
+        }
+    }
+}
+
+// Accelerometer sensitivity range in g.
+#[derive(Clone, Copy, Default, Debug, Eq, PartialEq, Hash)]
+pub enum AccelSensitivity {
+    G2,
+    G4,
+    #[default]
+    G8,
+    G16,
+}
+
+impl AccelSensitivity {
+    pub fn range(&self) -> f32 {
+        match self {
+            Self::G2 => 2.0,
+            Self::G4 => 4.0,
+            Self::G8 => 8.0,
+            Self::G16 => 16.0,
+        }
+    }
+
+    // The value in g of a single raw step.
+    pub fn coeff(&self) -> f32 {
+        self.range() * 2.0 / SENSOR_RES
+    }
+}
+
+// Gyroscope sensitivity range in degrees per second.
+#[derive(Clone, Copy, Default, Debug, Eq, PartialEq, Hash)]
+pub enum GyroSensitivity {
+    Dps250,
+    Dps500,
+    Dps1000,
+    #[default]
+    Dps2000,
+}
+
+impl GyroSensitivity {
+    pub fn range(&self) -> f32 {
+        match self {
+            Self::Dps250 => 250.0,
+            Self::Dps500 => 500.0,
+            Self::Dps1000 => 1000.0,
+            Self::Dps2000 => 2000.0,
+        }
+    }
+
+    // The value in degrees per second of a single raw step.
+    pub fn coeff(&self) -> f32 {
+        self.range() * 2.0 * GYROSCOPE_GAIN_HEADROOM / SENSOR_RES
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ImuStateConfig {
+    pub accel_sensitivity: AccelSensitivity,
+    pub gyro_sensitivity: GyroSensitivity,
+    // Inverts both accelerometer and gyroscope values of the axis.
+    pub invert_x: bool,
+    pub invert_y: bool,
+    pub invert_z: bool,
+}
 
 /// 6-Axis sensor state
 /// https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/imu_sensor_notes.md
 ///
-/// TODO: Currently only supports for gyro y/z axes state.
+/// Accelerometer values are in g and gyroscope values are in degrees per second.
 #[derive(Clone, Debug)]
 pub struct ImuState {
-    x: i32,
-    y: i32,
-    token: usize,
-    // z: u16,
+    accel: Vector3,
+    gyro: Vector3,
+    accel_sensitivity: AccelSensitivity,
+    gyro_sensitivity: GyroSensitivity,
+    invert: [bool; 3],
+    orientation: Option<OrientationTarget>,
 }
 
 impl ImuState {
-    // TODO: add invert axes options
     pub fn new() -> Self {
+        Self::with_config(Default::default())
+    }
+
+    pub fn with_config(config: ImuStateConfig) -> Self {
         Self {
-            x: 0,
-            y: 0,
-            token: 0,
-            /* z: 0 */
+            accel: Vector3::new(0.0, 0.0, GRAVITY),
+            gyro: Vector3::default(),
+            accel_sensitivity: config.accel_sensitivity,
+            gyro_sensitivity: config.gyro_sensitivity,
+            invert: [config.invert_x, config.invert_y, config.invert_z],
+            orientation: None,
         }
     }
 
-    pub fn set_horizontal(&mut self, x: u16) {
-        self.x = if x & 0x8000 != 0 {
-            (x ^ 0xFFFF) as i32 * -1 - 1
-        } else {
-            x as i32
-        };
-        self.token += 1;
+    pub fn accel(&self) -> Vector3 {
+        self.accel
     }
 
-    pub fn set_vertical(&mut self, y: u16) {
-        self.y = if y & 0x8000 != 0 {
-            (y ^ 0xFFFF) as i32 * -1 - 1
-        } else {
-            y as i32
+    pub fn set_accel(&mut self, accel: Vector3) -> Result<(), StateError> {
+        if !is_finite(&accel) {
+            return Err(StateError::InvalidRange);
+        }
+        self.accel = accel;
+        self.orientation = None;
+        Ok(())
+    }
+
+    pub fn gyro(&self) -> Vector3 {
+        self.gyro
+    }
+
+    pub fn set_gyro(&mut self, gyro: Vector3) -> Result<(), StateError> {
+        if !is_finite(&gyro) {
+            return Err(StateError::InvalidRange);
+        }
+        self.gyro = gyro;
+        self.orientation = None;
+        Ok(())
+    }
+
+    pub fn accel_sensitivity(&self) -> AccelSensitivity {
+        self.accel_sensitivity
+    }
+
+    pub fn set_accel_sensitivity(&mut self, sensitivity: AccelSensitivity) {
+        self.accel_sensitivity = sensitivity;
+    }
+
+    pub fn gyro_sensitivity(&self) -> GyroSensitivity {
+        self.gyro_sensitivity
+    }
+
+    pub fn set_gyro_sensitivity(&mut self, sensitivity: GyroSensitivity) {
+        self.gyro_sensitivity = sensitivity;
+    }
+
+    pub fn orientation(&self) -> Option<Quaternion> {
+        self.orientation.map(|orientation| orientation.target)
+    }
+
+    // Sets the target orientation and the linear acceleration in g (excluding
+    // gravity) of the controller body. The controller moves towards the target
+    // over the time since the previous orientation, from which the
+    // accelerometer and gyroscope values of each sample are derived.
+    pub fn set_orientation(
+        &mut self,
+        orientation: Quaternion,
+        linear_accel: Option<Vector3>,
+    ) -> Result<(), StateError> {
+        self.set_orientation_at(orientation, linear_accel, Instant::now())
+    }
+
+    pub fn set_orientation_at(
+        &mut self,
+        orientation: Quaternion,
+        linear_accel: Option<Vector3>,
+        now: Instant,
+    ) -> Result<(), StateError> {
+        let Some(orientation) = orientation.normalize() else {
+            return Err(StateError::InvalidRange);
         };
-        self.token += 1;
+        let linear_accel = linear_accel.unwrap_or_default();
+        if !is_finite(&linear_accel) {
+            return Err(StateError::InvalidRange);
+        }
+        self.orientation = Some(match &self.orientation {
+            Some(prev) => prev.retarget(orientation, linear_accel, now),
+            None => {
+                // No motion is derived from the first orientation.
+                self.gyro = Vector3::default();
+                OrientationTarget::new(orientation, linear_accel, now)
+            }
+        });
+        Ok(())
+    }
+
+    // Same as `set_orientation`, with the Euler angles in radians.
+    pub fn set_euler_angles(
+        &mut self,
+        roll: f32,
+        pitch: f32,
+        yaw: f32,
+        linear_accel: Option<Vector3>,
+    ) -> Result<(), StateError> {
+        self.set_orientation(Quaternion::from_euler(roll, pitch, yaw), linear_accel)
     }
 
+    pub fn set_euler_angles_at(
+        &mut self,
+        roll: f32,
+        pitch: f32,
+        yaw: f32,
+        linear_accel: Option<Vector3>,
+        now: Instant,
+    ) -> Result<(), StateError> {
+        self.set_orientation_at(Quaternion::from_euler(roll, pitch, yaw), linear_accel, now)
+    }
+
+    // Resets to the resting state, lying flat on a table.
+    pub fn reset(&mut self) {
+        self.accel = Vector3::new(0.0, 0.0, GRAVITY);
+        self.gyro = Vector3::default();
+        self.orientation = None;
+    }
+
+    // Encodes a single 12 bytes sample of the current state.
+    pub fn to_sample(&self) -> [u8; 12] {
+        self.encode_sample(&self.accel, &self.gyro)
+    }
+
+    // Encodes 3 samples of a report. When the orientation is supplied, the
+    // samples are taken at 5ms interval up to now along the motion.
     pub fn to_buf(&mut self) -> [u8; 36] {
-        let gyro_x: i32 = 0;
-        let gyro_y: i32 = ((self.y / DEFAULT_SENSITIVITY) as f32 * GYROSCOPE_SENSITIVITY_MULTIPLIER
-            / GYROSCOPE_COEFF) as i32;
-        let gyro_z: i32 = (-(self.x / DEFAULT_SENSITIVITY) as f32
-            * GYROSCOPE_SENSITIVITY_MULTIPLIER
-            / GYROSCOPE_COEFF) as i32;
-        // The 6-axis data is repeated 3 times.
+        self.to_buf_at(Instant::now())
+    }
+
+    pub fn to_buf_at(&mut self, now: Instant) -> [u8; 36] {
         let mut buf = [0u8; 36];
-        if self.token > 0 {
-            buf.fill(0x7F);
-            self.token -= 1;
-        }
-        // Gyro 1 / round 1
-        buf[6] = (gyro_x & 0xFF) as u8;
-        buf[7] = ((gyro_x >> 8) & 0xFF) as u8;
-        // Gyro 1 / round 2
-        buf[18] = (gyro_x & 0xFF) as u8;
-        buf[19] = ((gyro_x >> 8) & 0xFF) as u8;
-        // Gyro 1 / round 3
-        buf[30] = (gyro_x & 0xFF) as u8;
-        buf[31] = ((gyro_x >> 8) & 0xFF) as u8;
-        // Gyro 2 / round 1
-        buf[8] = (gyro_y & 0xFF) as u8;
-        buf[9] = ((gyro_y >> 8) & 0xFF) as u8;
-        // Gyro 2 / round 2
-        buf[20] = (gyro_y & 0xFF) as u8;
-        buf[21] = ((gyro_y >> 8) & 0xFF) as u8;
-        // Gyro 2 / round 3
-        buf[32] = (gyro_y & 0xFF) as u8;
-        buf[33] = ((gyro_y >> 8) & 0xFF) as u8;
-        // Gyro 3 / round 1
-        buf[10] = (gyro_z & 0xFF) as u8;
-        buf[11] = ((gyro_z >> 8) & 0xFF) as u8;
-        // Gyro 3 / round 2
-        buf[22] = (gyro_z & 0xFF) as u8;
-        buf[23] = ((gyro_z >> 8) & 0xFF) as u8;
-        // Gyro 3 / round 3
-        buf[34] = (gyro_z & 0xFF) as u8;
-        buf[35] = ((gyro_z >> 8) & 0xFF) as u8;
-        // FIXME: to remove debug code (tracing crate also)
-        tracing::info!(
-            "@@@ imu state: x: {:?} y: {:?} gyro(x,y,z): {},{},{}, buf: {:?}",
-            self.x,
-            self.y,
-            gyro_x,
-            gyro_y,
-            gyro_z,
-            &buf
-        );
+        let Some(orientation) = self.orientation else {
+            let sample = self.to_sample();
+            for chunk in buf.chunks_exact_mut(12) {
+                chunk.copy_from_slice(&sample);
+            }
+            return buf;
+        };
+        let gravity = Vector3::new(0.0, 0.0, GRAVITY);
+        for (i, chunk) in (1..=SAMPLES_PER_REPORT).zip(buf.chunks_exact_mut(12)) {
+            let at = now
+                .checked_sub(SAMPLE_INTERVAL * (SAMPLES_PER_REPORT - i))
+                .unwrap_or(now);
+            let (q, linear_accel) = orientation.pose_at(at);
+            self.accel = q.conjugate().rotate(&gravity).add(&linear_accel);
+            self.gyro = orientation.gyro_at(at);
+            chunk.copy_from_slice(&self.encode_sample(&self.accel, &self.gyro));
+        }
+        buf
+    }
+
+    // Encodes a single 12 bytes sample: accelerometer X/Y/Z followed by
+    // gyroscope X/Y/Z, each as a little endian i16.
+    fn encode_sample(&self, accel: &Vector3, gyro: &Vector3) -> [u8; 12] {
+        let accel_coeff = self.accel_sensitivity.coeff();
+        let gyro_coeff = self.gyro_sensitivity.coeff();
+        let values = [
+            accel.x / accel_coeff,
+            accel.y / accel_coeff,
+            accel.z / accel_coeff,
+            gyro.x / gyro_coeff,
+            gyro.y / gyro_coeff,
+            gyro.z / gyro_coeff,
+        ];
+        let mut buf = [0u8; 12];
+        for (i, value) in values.into_iter().enumerate() {
+            let value = if self.invert[i % 3] { -value } else { value };
+            let raw = value.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            buf[i * 2..i * 2 + 2].copy_from_slice(&raw.to_le_bytes());
+        }
         buf
     }
 }
+
+fn is_finite(v: &Vector3) -> bool {
+    v.x.is_finite() && v.y.is_finite() && v.z.is_finite()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AccelSensitivity, GyroSensitivity, ImuState, ImuStateConfig, Quaternion, Vector3, GRAVITY,
+    };
+    use tokio::time::{Duration, Instant};
+
+    fn raw_values(sample: &[u8]) -> Vec<i16> {
+        sample
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn encode_resting_state() {
+        let mut imu = ImuState::new();
+        let buf = imu.to_buf();
+        // 1g is 4096 steps in the ±8g range.
+        let expected = vec![0, 0, 4096, 0, 0, 0];
+        for sample in buf.chunks_exact(12) {
+            assert_eq!(raw_values(sample), expected);
+        }
+    }
+
+    #[test]
+    fn encode_with_sensitivity() {
+        let mut imu = ImuState::with_config(ImuStateConfig {
+            accel_sensitivity: AccelSensitivity::G2,
+            gyro_sensitivity: GyroSensitivity::Dps2000,
+            ..Default::default()
+        });
+        imu.set_accel(Vector3::new(-0.5, 0.25, GRAVITY)).unwrap();
+        imu.set_gyro(Vector3::new(70.0, -7.0, 4000.0)).unwrap();
+        assert_eq!(
+            raw_values(&imu.to_sample()),
+            vec![-8192, 4096, 16384, 1000, -100, i16::MAX]
+        );
+
+        imu.set_gyro_sensitivity(GyroSensitivity::Dps250);
+        assert_eq!(raw_values(&imu.to_sample())[4], -800);
+        assert!(imu.set_gyro(Vector3::new(f32::NAN, 0.0, 0.0)).is_err());
+    }
+
+    #[test]
+    fn encode_inverted_axes() {
+        let mut imu = ImuState::with_config(ImuStateConfig {
+            invert_y: true,
+            invert_z: true,
+            ..Default::default()
+        });
+        imu.set_gyro(Vector3::new(7.0, 7.0, 7.0)).unwrap();
+        assert_eq!(
+            raw_values(&imu.to_sample()),
+            vec![0, 0, -4096, 100, -100, -100]
+        );
+    }
+
+    #[test]
+    fn quaternion_rotation() {
+        let q = Quaternion::from_euler(0.0, 0.0, std::f32::consts::FRAC_PI_2);
+        let v = q.rotate(&Vector3::new(1.0, 0.0, 0.0));
+        assert!((v.x - 0.0).abs() < 1e-6 && (v.y - 1.0).abs() < 1e-6);
+        let r = q.to_rotation_vector();
+        assert!((r.z - std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+        let half = Quaternion::IDENTITY.slerp(&q, 0.5).to_rotation_vector();
+        assert!((half.z - std::f32::consts::FRAC_PI_4).abs() < 1e-6);
+    }
+
+    #[test]
+    fn synthesize_from_orientation() {
+        let mut imu = ImuState::new();
+        let t0 = Instant::now();
+        let ms = Duration::from_millis;
+        imu.set_orientation_at(Quaternion::IDENTITY, None, t0)
+            .unwrap();
+        let buf = imu.to_buf_at(t0);
+        for sample in buf.chunks_exact(12) {
+            assert_eq!(raw_values(sample), vec![0, 0, 4096, 0, 0, 0]);
+        }
+        // Roll by 9 degrees 15ms after the previous orientation, that is 600
+        // deg/s, reported by the samples while the controller is moving.
+        imu.set_euler_angles_at(9f32.to_radians(), 0.0, 0.0, None, t0 + ms(15))
+            .unwrap();
+        let buf = imu.to_buf_at(t0 + ms(30));
+        let samples: Vec<Vec<i16>> = buf.chunks_exact(12).map(raw_values).collect();
+        let gyro_coeff = imu.gyro_sensitivity().coeff();
+        for sample in &samples {
+            assert!((f32::from(sample[3]) - 600.0 / gyro_coeff).abs() <= 1.0);
+            assert_eq!(sample[4], 0);
+        }
+        // Gravity is projected onto the rolled Y/Z axes.
+        let expected_y = (4096.0 * 9f32.to_radians().sin()).round() as i16;
+        let expected_z = (4096.0 * 9f32.to_radians().cos()).round() as i16;
+        assert_eq!(samples[2][1], expected_y);
+        assert_eq!(samples[2][2], expected_z);
+        assert!(samples[0][1] < samples[1][1] && samples[1][1] < samples[2][1]);
+        // Holding the orientation settles the gyro rates to zero.
+        let buf = imu.to_buf_at(t0 + ms(45));
+        assert_eq!(raw_values(&buf[24..36])[3], 0);
+        assert_eq!(raw_values(&buf[24..36])[1], expected_y);
+        // Rates follow the time since the previous orientation rather than
+        // the report rate, e.g. rolling back 30ms later is -300 deg/s.
+        imu.set_orientation_at(Quaternion::IDENTITY, None, t0 + ms(45))
+            .unwrap();
+        let buf = imu.to_buf_at(t0 + ms(50));
+        let sample = raw_values(&buf[24..36]);
+        assert!((f32::from(sample[3]) + 300.0 / gyro_coeff).abs() <= 1.0);
+        assert!(imu
+            .set_orientation(Quaternion::new(0.0, 0.0, 0.0, 0.0), None)
+            .is_err());
+    }
+}
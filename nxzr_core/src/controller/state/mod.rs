@@ -1,10 +1,12 @@
 use super::{spi_flash::SpiFlash, ControllerType};
 use button::ButtonState;
-use imu::ImuState;
+use imu::{ImuState, ImuStateConfig};
+use power::{PowerState, PowerStateConfig};
 use stick::{StickCalibration, StickState, StickStateConfig};
 
 pub mod button;
 pub mod imu;
+pub mod power;
 pub mod stick;
 
 #[derive(Clone, Debug, thiserror::Error)]
@@ -27,6 +29,8 @@ pub enum StateError {
 pub struct ControllerStateConfig {
     pub controller: ControllerType,
     pub spi_flash: Option<SpiFlash>,
+    pub imu: ImuStateConfig,
+    pub power: PowerStateConfig,
 }
 
 #[derive(Clone, Debug)]
@@ -36,6 +40,7 @@ pub struct ControllerState {
     l_stick_state: StickState,
     r_stick_state: StickState,
     imu_state: ImuState,
+    power_state: PowerState,
 }
 
 impl ControllerState {
@@ -77,7 +82,8 @@ impl ControllerState {
                     button_state: ButtonState::with_controller(config.controller),
                     l_stick_state,
                     r_stick_state,
-                    imu_state: ImuState::new(),
+                    imu_state: ImuState::with_config(config.imu),
+                    power_state: PowerState::with_config(config.power)?,
                 })
             }
             None => Ok(Self {
@@ -85,7 +91,8 @@ impl ControllerState {
                 button_state: ButtonState::with_controller(config.controller),
                 l_stick_state: StickState::new(),
                 r_stick_state: StickState::new(),
-                imu_state: ImuState::new(),
+                imu_state: ImuState::with_config(config.imu),
+                power_state: PowerState::with_config(config.power)?,
             }),
         }
     }
@@ -125,4 +132,12 @@ impl ControllerState {
     pub fn imu_state_mut(&mut self) -> &mut ImuState {
         &mut self.imu_state
     }
+
+    pub fn power_state(&self) -> &PowerState {
+        &self.power_state
+    }
+
+    pub fn power_state_mut(&mut self) -> &mut PowerState {
+        &mut self.power_state
+    }
 }
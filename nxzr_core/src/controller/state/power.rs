@@ -0,0 +1,196 @@
+use super::StateError;
+use crate::controller::ControllerType;
+use tokio::time::{Duration, Instant};
+
+pub const MAX_BATTERY_LEVEL: u8 = 4;
+
+#[derive(Debug)]
+pub struct PowerStateConfig {
+    // Battery level in the range of [0, 4], 0 is empty and 4 is full.
+    pub battery_level: u8,
+    pub is_charging: bool,
+    // Powered by the Switch, the charging grip or USB.
+    pub is_powered: bool,
+}
+
+impl Default for PowerStateConfig {
+    fn default() -> Self {
+        Self {
+            battery_level: MAX_BATTERY_LEVEL,
+            is_charging: false,
+            is_powered: false,
+        }
+    }
+}
+
+// Simulated battery drain, which decreases the battery level by one for each
+// interval unless it's charging.
+#[derive(Clone, Copy, Debug)]
+struct BatteryDrain {
+    interval: Duration,
+    started_at: Instant,
+    start_level: u8,
+}
+
+#[derive(Clone, Debug)]
+pub struct PowerState {
+    battery_level: u8,
+    is_charging: bool,
+    is_powered: bool,
+    drain: Option<BatteryDrain>,
+}
+
+impl Default for PowerState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PowerState {
+    pub fn new() -> Self {
+        Self::with_config(Default::default()).unwrap()
+    }
+
+    pub fn with_config(config: PowerStateConfig) -> Result<Self, StateError> {
+        if config.battery_level > MAX_BATTERY_LEVEL {
+            return Err(StateError::InvalidRange);
+        }
+        Ok(Self {
+            battery_level: config.battery_level,
+            is_charging: config.is_charging,
+            is_powered: config.is_powered,
+            drain: None,
+        })
+    }
+
+    pub fn battery_level(&self) -> u8 {
+        self.battery_level_at(Instant::now())
+    }
+
+    pub fn battery_level_at(&self, now: Instant) -> u8 {
+        let Some(drain) = self.drain else {
+            return self.battery_level;
+        };
+        if self.is_charging {
+            return self.battery_level;
+        }
+        let elapsed = now.saturating_duration_since(drain.started_at);
+        let drained = elapsed.as_nanos() / drain.interval.as_nanos().max(1);
+        drain
+            .start_level
+            .saturating_sub(drained.min(u8::MAX.into()) as u8)
+    }
+
+    pub fn set_battery_level(&mut self, battery_level: u8) -> Result<(), StateError> {
+        if battery_level > MAX_BATTERY_LEVEL {
+            return Err(StateError::InvalidRange);
+        }
+        self.battery_level = battery_level;
+        self.restart_drain();
+        Ok(())
+    }
+
+    pub fn is_charging(&self) -> bool {
+        self.is_charging
+    }
+
+    pub fn set_charging(&mut self, is_charging: bool) {
+        // Fixes the drained level at the moment.
+        self.battery_level = self.battery_level();
+        self.is_charging = is_charging;
+        self.restart_drain();
+    }
+
+    pub fn is_powered(&self) -> bool {
+        self.is_powered
+    }
+
+    pub fn set_powered(&mut self, is_powered: bool) {
+        self.is_powered = is_powered;
+    }
+
+    // Starts draining the battery by one level for each interval, or stops it
+    // if `None`.
+    pub fn set_drain(&mut self, interval: Option<Duration>) {
+        self.battery_level = self.battery_level();
+        self.drain = interval.map(|interval| BatteryDrain {
+            interval,
+            started_at: Instant::now(),
+            start_level: self.battery_level,
+        });
+    }
+
+    // Encodes the byte 3 of the standard input report.
+    //
+    // Bit      7-5             4           3-1                 0
+    //          battery level   charging    connection info     powered
+    pub fn to_byte(&self, controller_type: ControllerType) -> u8 {
+        self.to_byte_at(controller_type, Instant::now())
+    }
+
+    pub fn to_byte_at(&self, controller_type: ControllerType, now: Instant) -> u8 {
+        (self.battery_level_at(now) * 2) << 4
+            | u8::from(self.is_charging) << 4
+            | controller_type.connection_info()
+            | u8::from(self.is_powered)
+    }
+
+    fn restart_drain(&mut self) {
+        if let Some(drain) = self.drain.as_mut() {
+            drain.started_at = Instant::now();
+            drain.start_level = self.battery_level;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PowerState, PowerStateConfig};
+    use crate::controller::ControllerType;
+    use tokio::time::{Duration, Instant};
+
+    #[test]
+    fn encode_power_state() {
+        let power_state = PowerState::new();
+        assert_eq!(power_state.to_byte(ControllerType::JoyConL), 0x8E);
+        assert_eq!(power_state.to_byte(ControllerType::ProController), 0x80);
+
+        let power_state = PowerState::with_config(PowerStateConfig {
+            battery_level: 1,
+            is_charging: true,
+            is_powered: true,
+        })
+        .unwrap();
+        assert_eq!(power_state.to_byte(ControllerType::ProController), 0x31);
+        assert!(PowerState::with_config(PowerStateConfig {
+            battery_level: 5,
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn drain_battery() {
+        let mut power_state = PowerState::new();
+        power_state.set_drain(Some(Duration::from_secs(60)));
+        let now = Instant::now();
+        assert_eq!(power_state.battery_level_at(now), 4);
+        assert_eq!(
+            power_state.battery_level_at(now + Duration::from_secs(150)),
+            2
+        );
+        assert_eq!(
+            power_state.battery_level_at(now + Duration::from_secs(3600)),
+            0
+        );
+        power_state.set_charging(true);
+        assert_eq!(
+            power_state.battery_level_at(now + Duration::from_secs(3600)),
+            4
+        );
+        assert_eq!(
+            power_state.to_byte_at(ControllerType::JoyConR, now + Duration::from_secs(3600)),
+            0x9E
+        );
+    }
+}
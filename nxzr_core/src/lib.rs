@@ -1,5 +1,12 @@
+pub mod capture;
 pub mod controller;
+pub mod metrics;
 pub mod protocol;
+pub mod replay;
+#[cfg(test)]
+mod test_utils;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod virtual_host;
 
 // Re-export of address module.
 pub use nxzr_shared::addr::*;
@@ -0,0 +1,175 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+// Upper bound of the first bucket, doubled for every following bucket.
+const HISTOGRAM_BASE: Duration = Duration::from_micros(500);
+// 0.5ms up to 512ms, then the overflow bucket.
+const HISTOGRAM_BUCKETS: usize = 12;
+// Window to measure the rate over.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+// Histogram of durations with exponential buckets, which is cheap enough to
+// be updated for every report.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DurationHistogram {
+    buckets: [u64; HISTOGRAM_BUCKETS],
+    count: u64,
+    sum: Duration,
+    max: Duration,
+}
+
+impl DurationHistogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, duration: Duration) {
+        let index = (0..HISTOGRAM_BUCKETS - 1)
+            .find(|i| duration <= Self::upper_bound(*i))
+            .unwrap_or(HISTOGRAM_BUCKETS - 1);
+        self.buckets[index] += 1;
+        self.count += 1;
+        self.sum += duration;
+        self.max = self.max.max(duration);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(self.sum.div_f64(self.count as f64))
+    }
+
+    // Estimates the percentile in the range of [0.0, 1.0] by the upper bound
+    // of the bucket it falls in, capped by the max.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((self.count as f64 * percentile.clamp(0.0, 1.0)).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(match i {
+                    i if i == HISTOGRAM_BUCKETS - 1 => self.max,
+                    i => Self::upper_bound(i).min(self.max),
+                });
+            }
+        }
+        Some(self.max)
+    }
+
+    // Pairs of the bucket upper bound and the count, the last one is
+    // unbounded.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        self.buckets.iter().enumerate().map(|(i, count)| {
+            let upper_bound = (i < HISTOGRAM_BUCKETS - 1).then(|| Self::upper_bound(i));
+            (upper_bound, *count)
+        })
+    }
+
+    fn upper_bound(index: usize) -> Duration {
+        HISTOGRAM_BASE * (1 << index)
+    }
+}
+
+// Counts the occurrences, along with the rate measured over the last
+// completed window.
+#[derive(Clone, Debug, Default)]
+pub struct RateCounter {
+    total: u64,
+    window_started_at: Option<Instant>,
+    window_count: u64,
+    // Rate of the window before the current one.
+    last_per_second: f64,
+}
+
+impl RateCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, now: Instant) {
+        self.total += 1;
+        let window_started_at = *self.window_started_at.get_or_insert(now);
+        let elapsed = now.saturating_duration_since(window_started_at);
+        if elapsed >= RATE_WINDOW {
+            self.last_per_second = self.window_count as f64 / elapsed.as_secs_f64();
+            self.window_started_at = Some(now);
+            self.window_count = 0;
+        }
+        self.window_count += 1;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    // The current window counts as completed once it's over, so that the rate
+    // drops while nothing is recorded.
+    pub fn per_second(&self, now: Instant) -> f64 {
+        let Some(window_started_at) = self.window_started_at else {
+            return 0.0;
+        };
+        let elapsed = now.saturating_duration_since(window_started_at);
+        if elapsed >= RATE_WINDOW {
+            self.window_count as f64 / elapsed.as_secs_f64()
+        } else {
+            self.last_per_second
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DurationHistogram, RateCounter};
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    #[test]
+    fn record_durations() {
+        let mut histogram = DurationHistogram::new();
+        assert_eq!(histogram.percentile(0.5), None);
+        for millis in [1, 1, 2, 3, 8, 8, 9, 2000] {
+            histogram.record(Duration::from_millis(millis));
+        }
+        assert_eq!(histogram.count(), 8);
+        assert_eq!(histogram.max(), Duration::from_secs(2));
+        assert_eq!(histogram.mean(), Some(Duration::from_micros(254_000)));
+        assert_eq!(histogram.percentile(0.25), Some(Duration::from_millis(1)));
+        assert_eq!(histogram.percentile(0.5), Some(Duration::from_millis(4)));
+        assert_eq!(histogram.percentile(0.75), Some(Duration::from_millis(8)));
+        assert_eq!(histogram.percentile(1.0), Some(Duration::from_secs(2)));
+        let (upper_bound, count) = histogram.buckets().last().unwrap();
+        assert_eq!(upper_bound, None);
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn count_rate() {
+        let mut counter = RateCounter::new();
+        let started_at = Instant::now();
+        assert_eq!(counter.per_second(started_at), 0.0);
+        for i in 0..=240 {
+            counter.record(started_at + Duration::from_secs_f64(f64::from(i) / 120.0));
+        }
+        assert_eq!(counter.total(), 241);
+        let stopped_at = started_at + Duration::from_secs(2);
+        assert!((counter.per_second(stopped_at) - 120.0).abs() < 1.0);
+        // Drops after the records have stopped.
+        assert!(counter.per_second(stopped_at + Duration::from_secs(10)) < 1.0);
+    }
+}
@@ -1,9 +1,15 @@
 use crate::controller::{
+    ir_camera::IrFrame,
+    light::{HomeLight, PlayerLights},
+    mcu::McuMode,
+    nfc_tag::NfcTag,
     protocol::{
         ControllerProtocol, ControllerProtocolError, Event as ProtocolEvent,
         LogType as ProtocolLogType,
     },
     report::subcommand::Subcommand,
+    rumble::Rumble,
+    spi_flash::SpiFlash,
     state::ControllerState,
 };
 use nxzr_shared::{
@@ -13,14 +19,16 @@ use nxzr_shared::{
 use std::future::Future;
 use std::sync::Arc;
 use strum::{Display, IntoStaticStr};
-use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
 use tokio::task::JoinSet;
 use tokio::time::{self, Duration};
 
 // Re-exports subset of internal protocol module exports.
 pub use crate::controller::protocol::{
-    ControllerProtocolConfig as ProtocolConfig, TransportRead, TransportWrite,
+    ControllerProtocolConfig as ProtocolConfig, LaggedWriteStats, ProtocolMetrics,
+    ProtocolSnapshot, TransportRead, TransportWrite,
 };
+pub use crate::controller::sequence::{Hold, InputSequence};
 
 #[derive(Clone, Debug, thiserror::Error)]
 pub enum ProtocolError {
@@ -103,6 +111,11 @@ impl Protocol {
                         let evt = match orig {
                             ProtocolEvent::Warning(err) => Event::Warning(err.into()),
                             ProtocolEvent::Log(log) => Event::Log(log.into()),
+                            ProtocolEvent::Rumble(rumble) => Event::Rumble(rumble),
+                            ProtocolEvent::PlayerLights(player_lights) => {
+                                Event::PlayerLights(player_lights)
+                            }
+                            ProtocolEvent::HomeLight(home_light) => Event::HomeLight(home_light),
                         };
                         let _ = msg_tx.try_send(evt);
                     }
@@ -259,6 +272,64 @@ impl Protocol {
         self.inner.update_controller_state(f).await
     }
 
+    // Apply the sequence of the controller state changes to the input reports
+    // sent from now on, e.g. to press a button for an exact number of reports.
+    // Resolved once the whole sequence has been sent.
+    pub async fn send_sequence(&self, sequence: InputSequence) -> Result<(), ProtocolError> {
+        self.inner.send_sequence(sequence).await
+    }
+
+    // Get a copy of the current controller state.
+    pub fn controller_state(&self) -> ControllerState {
+        self.inner.protocol.controller_state()
+    }
+
+    // Get the current state of the protocol, e.g. whether it's pairing.
+    pub fn snapshot(&self) -> ProtocolSnapshot {
+        self.inner.protocol.snapshot()
+    }
+
+    // Get the performance metrics collected so far, e.g. the write latency.
+    pub fn metrics(&self) -> ProtocolMetrics {
+        self.inner.protocol.metrics()
+    }
+
+    // Watch the state of the protocol, which changes when the pairing ends,
+    // or the report mode or the send interval changes.
+    pub fn watch_snapshot(&self) -> watch::Receiver<ProtocolSnapshot> {
+        self.inner.protocol.watch_snapshot()
+    }
+
+    // Get a copy of the current SPI flash image.
+    pub fn spi_flash(&self) -> Option<SpiFlash> {
+        self.inner.protocol.spi_flash()
+    }
+
+    // Put the NFC tag (e.g. amiibo) on the virtual reader, or take it off if `None`.
+    pub fn set_nfc_tag(&self, nfc_tag: Option<NfcTag>) {
+        self.inner.protocol.set_nfc_tag(nfc_tag)
+    }
+
+    // Get the NFC tag currently on the virtual reader.
+    pub fn nfc_tag(&self) -> Option<NfcTag> {
+        self.inner.protocol.nfc_tag()
+    }
+
+    // Push the grayscale frame to be sent as the IR camera image.
+    pub fn push_ir_frame(&self, frame: IrFrame) {
+        self.inner.protocol.push_ir_frame(frame)
+    }
+
+    // Get the player lights pattern set by the host.
+    pub fn player_lights(&self) -> PlayerLights {
+        self.inner.protocol.player_lights()
+    }
+
+    // Get the home light pattern set by the host, if any.
+    pub fn home_light(&self) -> Option<HomeLight> {
+        self.inner.protocol.home_light()
+    }
+
     // Listen for the protocol control events.
     pub async fn events(&self) -> Result<mpsc::UnboundedReceiver<Event>, ProtocolError> {
         self.inner.events().await
@@ -320,6 +391,14 @@ impl ProtocolInner {
         }
     }
 
+    pub async fn send_sequence(&self, sequence: InputSequence) -> Result<(), ProtocolError> {
+        self.protocol.writer_ready().await;
+        tokio::select! {
+            res = self.protocol.send_sequence(sequence) => Ok(res?),
+            _ = self.closing_tx.closed() => Err(ProtocolError::ActionAbortedDueToClosing),
+        }
+    }
+
     pub async fn events(&self) -> Result<mpsc::UnboundedReceiver<Event>, ProtocolError> {
         Event::subscribe(&mut self.event_sub_tx.clone())
             .await
@@ -400,6 +479,9 @@ pub enum Event {
     Log(LogType),
     Error(ProtocolError),
     Warning(ProtocolError),
+    Rumble(Rumble),
+    PlayerLights(PlayerLights),
+    HomeLight(HomeLight),
 }
 
 impl std::fmt::Display for Event {
@@ -408,6 +490,11 @@ impl std::fmt::Display for Event {
             Self::Log(log) => write!(f, "[log]: {:?}", log),
             Self::Error(err) => write!(f, "[error]: {}", err.to_string()),
             Self::Warning(err) => write!(f, "[warn]: {}", err.to_string()),
+            Self::Rumble(rumble) => write!(f, "[rumble]: {:?}", rumble),
+            Self::PlayerLights(player_lights) => {
+                write!(f, "[player lights]: {:?}", player_lights)
+            }
+            Self::HomeLight(home_light) => write!(f, "[home light]: {:?}", home_light),
         }
     }
 }
@@ -418,6 +505,14 @@ pub enum LogType {
     Closed,
     PairingEnded,
     SubcommandReceived(Subcommand),
+    SpiFlashWritten { offset: u32, size: u32 },
+    ReportModeChanged(u8),
+    // `None` if the reports are written only on demand.
+    SendIntervalChanged(Option<Duration>),
+    ImuToggled { enabled: bool },
+    VibrationToggled { enabled: bool },
+    McuModeChanged(McuMode),
+    WritesLagged(LaggedWriteStats),
 }
 
 impl From<ProtocolLogType> for LogType {
@@ -425,6 +520,15 @@ impl From<ProtocolLogType> for LogType {
         match log_type {
             ProtocolLogType::PairingEnded => Self::PairingEnded,
             ProtocolLogType::SubcommandReceived(subcommand) => Self::SubcommandReceived(subcommand),
+            ProtocolLogType::SpiFlashWritten { offset, size } => {
+                Self::SpiFlashWritten { offset, size }
+            }
+            ProtocolLogType::ReportModeChanged(mode) => Self::ReportModeChanged(mode),
+            ProtocolLogType::SendIntervalChanged(interval) => Self::SendIntervalChanged(interval),
+            ProtocolLogType::ImuToggled { enabled } => Self::ImuToggled { enabled },
+            ProtocolLogType::VibrationToggled { enabled } => Self::VibrationToggled { enabled },
+            ProtocolLogType::McuModeChanged(mode) => Self::McuModeChanged(mode),
+            ProtocolLogType::WritesLagged(stats) => Self::WritesLagged(stats),
         }
     }
 }
@@ -0,0 +1,319 @@
+use crate::{
+    capture::{read_capture, CaptureError, CaptureRecord, Direction},
+    protocol::{Transport, TransportPause, TransportRead, TransportWrite},
+};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use std::{
+    collections::VecDeque,
+    fmt,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    sync::watch,
+    time::{self, Duration, Instant},
+};
+
+// Subcommand replies are compared from the ACK byte up to the end of the
+// standard input report, the rest varies with the timer and the controller
+// state.
+const SUBCOMMAND_REPLY_RANGE: std::ops::Range<usize> = 14..50;
+
+#[derive(Clone, Debug)]
+pub struct ReplayConfig {
+    // Playback speed of the recorded timing, e.g. `2.0` feeds the reports
+    // twice as fast. `f64::INFINITY` feeds them without any delay.
+    pub speed: f64,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self { speed: 1.0 }
+    }
+}
+
+// Transport which feeds the output reports recorded from the host, and
+// collects the input reports written by the protocol.
+#[derive(Debug, Clone)]
+pub struct ReplayTransport {
+    inner: Arc<ReplayInner>,
+}
+
+#[derive(Debug)]
+struct ReplayInner {
+    speed: f64,
+    reads: Mutex<VecDeque<(Duration, Bytes)>>,
+    started_at: Mutex<Option<Instant>>,
+    finished_tx: watch::Sender<bool>,
+    recorded_writes: Vec<Bytes>,
+    writes: Mutex<Vec<Bytes>>,
+}
+
+impl ReplayTransport {
+    pub fn new(records: Vec<CaptureRecord>, config: ReplayConfig) -> Self {
+        let started_at = records.first().map(|record| record.timestamp);
+        let mut reads = VecDeque::new();
+        let mut recorded_writes = vec![];
+        for record in records {
+            match record.direction {
+                Direction::Received => {
+                    let offset = started_at
+                        .and_then(|started_at| record.timestamp.duration_since(started_at).ok())
+                        .unwrap_or_default();
+                    reads.push_back((offset, record.report));
+                }
+                Direction::Sent => recorded_writes.push(record.report),
+            }
+        }
+        Self {
+            inner: Arc::new(ReplayInner {
+                speed: config.speed,
+                finished_tx: watch::channel(reads.is_empty()).0,
+                reads: Mutex::new(reads),
+                started_at: Mutex::new(None),
+                recorded_writes,
+                writes: Mutex::new(vec![]),
+            }),
+        }
+    }
+
+    pub fn open(path: impl AsRef<Path>, config: ReplayConfig) -> Result<Self, CaptureError> {
+        Ok(Self::new(read_capture(path)?, config))
+    }
+
+    // Whether all of the recorded output reports have been read.
+    pub fn is_finished(&self) -> bool {
+        *self.inner.finished_tx.borrow()
+    }
+
+    // Resolved when all of the recorded output reports have been read.
+    pub async fn finished(&self) {
+        let mut rx = self.inner.finished_tx.subscribe();
+        while !*rx.borrow() {
+            if rx.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    // Input reports written by the protocol so far.
+    pub fn writes(&self) -> Vec<Bytes> {
+        self.inner.writes.lock().unwrap().clone()
+    }
+
+    // Input reports recorded from the controller side.
+    pub fn recorded_writes(&self) -> &[Bytes] {
+        &self.inner.recorded_writes
+    }
+
+    // Compares the subcommand replies (0x21 reports) written by the protocol
+    // against the recorded ones in order.
+    pub fn diff_subcommand_replies(&self) -> Vec<ReplyMismatch> {
+        let writes = self.writes();
+        let expected = subcommand_replies(&self.inner.recorded_writes);
+        let actual = subcommand_replies(&writes);
+        let mut mismatches = vec![];
+        for index in 0..expected.len().max(actual.len()) {
+            let expected = expected
+                .get(index)
+                .map(|reply| Bytes::copy_from_slice(reply));
+            let actual = actual.get(index).map(|reply| Bytes::copy_from_slice(reply));
+            if expected != actual {
+                mismatches.push(ReplyMismatch {
+                    index,
+                    expected,
+                    actual,
+                });
+            }
+        }
+        mismatches
+    }
+
+    // Panics with the mismatched replies if any.
+    pub fn assert_subcommand_replies(&self) {
+        let mismatches = self.diff_subcommand_replies();
+        if !mismatches.is_empty() {
+            let message: Vec<String> = mismatches.iter().map(ToString::to_string).collect();
+            panic!(
+                "subcommand replies do not match with the recording:\n{}",
+                message.join("\n")
+            );
+        }
+    }
+}
+
+fn subcommand_replies(reports: &[Bytes]) -> Vec<&[u8]> {
+    reports
+        .iter()
+        .filter(|report| report.get(1) == Some(&0x21))
+        .map(|report| {
+            let end = SUBCOMMAND_REPLY_RANGE.end.min(report.len());
+            report
+                .get(SUBCOMMAND_REPLY_RANGE.start..end)
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+// Subcommand reply which differs from the recording.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReplyMismatch {
+    // Index of the reply among the subcommand replies.
+    pub index: usize,
+    pub expected: Option<Bytes>,
+    pub actual: Option<Bytes>,
+}
+
+impl fmt::Display for ReplyMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex = |reply: &Option<Bytes>| match reply {
+            Some(reply) => reply
+                .iter()
+                .map(|byte| format!("{byte:02X}"))
+                .collect::<Vec<_>>()
+                .join(" "),
+            None => "(none)".into(),
+        };
+        write!(
+            f,
+            "reply #{}:\n  expected: {}\n  actual:   {}",
+            self.index,
+            hex(&self.expected),
+            hex(&self.actual)
+        )
+    }
+}
+
+impl Transport for ReplayTransport {}
+
+#[async_trait]
+impl TransportRead for ReplayTransport {
+    async fn read(&self) -> std::io::Result<BytesMut> {
+        let next = self.inner.reads.lock().unwrap().pop_front();
+        let Some((offset, report)) = next else {
+            let _ = self.inner.finished_tx.send_replace(true);
+            // Nothing left to feed, the host just stays silent from now on.
+            std::future::pending::<()>().await;
+            unreachable!();
+        };
+        let started_at = *self
+            .inner
+            .started_at
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Instant::now);
+        if self.inner.speed.is_finite() && self.inner.speed > 0.0 {
+            time::sleep_until(started_at + offset.div_f64(self.inner.speed)).await;
+        }
+        if self.inner.reads.lock().unwrap().is_empty() {
+            let _ = self.inner.finished_tx.send_replace(true);
+        }
+        Ok(BytesMut::from(&report[..]))
+    }
+}
+
+#[async_trait]
+impl TransportWrite for ReplayTransport {
+    async fn write(&self, buf: Bytes) -> std::io::Result<()> {
+        self.inner.writes.lock().unwrap().push(buf);
+        Ok(())
+    }
+}
+
+impl TransportPause for ReplayTransport {
+    fn pause(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReplayConfig, ReplayTransport};
+    use crate::{
+        capture::{CaptureRecord, Direction},
+        controller::protocol::{ControllerProtocol, ControllerProtocolConfig},
+        test_utils::subcommand_report,
+    };
+    use bytes::Bytes;
+    use std::time::{Duration, SystemTime};
+
+    fn record(direction: Direction, millis: u64, prefix: &[u8]) -> CaptureRecord {
+        let mut report = vec![0u8; 50];
+        report[..prefix.len()].copy_from_slice(prefix);
+        CaptureRecord {
+            direction,
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(millis),
+            report: Bytes::from(report),
+        }
+    }
+
+    fn subcommand(millis: u64, subcommand: &[u8]) -> CaptureRecord {
+        record(Direction::Received, millis, &subcommand_report(subcommand))
+    }
+
+    fn reply(millis: u64, ack: u8, data: &[u8]) -> CaptureRecord {
+        let mut prefix = vec![0xA1, 0x21];
+        prefix.resize(14, 0x00);
+        prefix.push(ack);
+        prefix.extend_from_slice(data);
+        record(Direction::Sent, millis, &prefix)
+    }
+
+    #[tokio::test]
+    async fn replay_handshake() {
+        let replay = ReplayTransport::new(
+            vec![
+                // Set shipment state
+                subcommand(0, &[0x08, 0x00]),
+                reply(5, 0x80, &[0x08]),
+                // Set input report mode: standard full mode
+                subcommand(20, &[0x03, 0x30]),
+                reply(25, 0x80, &[0x03]),
+                // Get player lights
+                subcommand(40, &[0x31]),
+                reply(45, 0xB0, &[0x31, 0x00]),
+            ],
+            ReplayConfig { speed: 10.0 },
+        );
+        assert_eq!(replay.recorded_writes().len(), 3);
+        let protocol = ControllerProtocol::new(ControllerProtocolConfig::default()).unwrap();
+        while !replay.is_finished() {
+            protocol.process_read(&replay).await.unwrap();
+        }
+        replay.finished().await;
+        replay.assert_subcommand_replies();
+
+        // A different reply is reported.
+        let replay = ReplayTransport::new(
+            vec![subcommand(0, &[0x08, 0x00]), reply(5, 0x82, &[0x08])],
+            ReplayConfig {
+                speed: f64::INFINITY,
+            },
+        );
+        protocol.process_read(&replay).await.unwrap();
+        let mismatches = replay.diff_subcommand_replies();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].index, 0);
+        assert!(mismatches[0].to_string().contains("expected: 82 08 00"));
+    }
+
+    // Snapshot of the emulator's own replies, not of a real Switch session.
+    #[tokio::test]
+    async fn replay_handshake_snapshot() {
+        let replay = ReplayTransport::open(
+            concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/fixtures/handshake_snapshot.btsnoop"
+            ),
+            ReplayConfig {
+                speed: f64::INFINITY,
+            },
+        )
+        .unwrap();
+        assert_eq!(replay.recorded_writes().len(), 12);
+        let protocol = ControllerProtocol::new(ControllerProtocolConfig::default()).unwrap();
+        while !replay.is_finished() {
+            protocol.process_read(&replay).await.unwrap();
+        }
+        replay.assert_subcommand_replies();
+    }
+}
@@ -0,0 +1,11 @@
+// Helpers shared by the unit tests.
+
+// Output report of the subcommand with the neutral rumble data, padded to the
+// length sent by the Switch.
+pub(crate) fn subcommand_report(subcommand: &[u8]) -> Vec<u8> {
+    let mut report = vec![0xA2, 0x01, 0x00];
+    report.extend_from_slice(&[0x00, 0x01, 0x40, 0x40, 0x00, 0x01, 0x40, 0x40]);
+    report.extend_from_slice(subcommand);
+    report.resize(49, 0x00);
+    report
+}
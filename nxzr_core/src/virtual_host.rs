@@ -0,0 +1,389 @@
+use crate::{
+    controller::{
+        report::{
+            input::{InputReport, InputReportId},
+            output::{OutputReport, OutputReportId},
+            subcommand::Subcommand,
+            ReportError,
+        },
+        ControllerType,
+    },
+    protocol::{Transport, TransportPause, TransportRead, TransportWrite},
+};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use tokio::{
+    sync::{mpsc, Mutex},
+    time::{self, Duration, Instant},
+};
+
+// Emulates the console side of the protocol over an in-memory transport, so
+// that the whole pipeline can be exercised without the Bluetooth hardware.
+//
+// Ref: https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/bluetooth_hid_subcommands_notes.md
+
+// Neutral rumble data, sent along with every output report.
+const NEUTRAL_RUMBLE: [u8; 8] = [0x00, 0x01, 0x40, 0x40, 0x00, 0x01, 0x40, 0x40];
+// How long to wait for a reply before giving up.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum VirtualHostError {
+    #[error("timed out waiting for the controller")]
+    Timeout,
+    #[error("transport is closed")]
+    Closed,
+    #[error("unexpected reply to {subcommand}: {message}")]
+    UnexpectedReply {
+        subcommand: Subcommand,
+        message: String,
+    },
+    #[error("report: {0}")]
+    Report(ReportError),
+}
+
+impl From<ReportError> for VirtualHostError {
+    fn from(err: ReportError) -> Self {
+        Self::Report(err)
+    }
+}
+
+// Summary of the controller learned by the handshake.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HandshakeInfo {
+    pub controller_type: Option<ControllerType>,
+    pub address: [u8; 6],
+    pub serial_number: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct VirtualHost {
+    output_tx: mpsc::UnboundedSender<BytesMut>,
+    input_rx: Mutex<mpsc::UnboundedReceiver<(Instant, Bytes)>>,
+    packet_counter: std::sync::Mutex<u64>,
+}
+
+impl VirtualHost {
+    // Creates a host with the transport to connect the protocol to.
+    pub fn new() -> (Self, VirtualTransport) {
+        let (output_tx, output_rx) = mpsc::unbounded_channel();
+        let (input_tx, input_rx) = mpsc::unbounded_channel();
+        (
+            Self {
+                output_tx,
+                input_rx: Mutex::new(input_rx),
+                packet_counter: std::sync::Mutex::new(0),
+            },
+            VirtualTransport {
+                inner: Arc::new(VirtualTransportInner {
+                    output_rx: Mutex::new(output_rx),
+                    input_tx,
+                    is_paused: AtomicBool::new(false),
+                }),
+            },
+        )
+    }
+
+    // Runs the handshake of the console, from the device info request up to
+    // the player lights, which makes the protocol ready to write.
+    pub async fn handshake(&self) -> Result<HandshakeInfo, VirtualHostError> {
+        let reply = self
+            .request_subcommand(Subcommand::RequestDeviceInfo, &[])
+            .await?;
+        let data = reply.subcommand_reply_data();
+        let (Some(&id), Some(address_bytes)) = (data.get(2), data.get(4..10)) else {
+            return Err(VirtualHostError::UnexpectedReply {
+                subcommand: Subcommand::RequestDeviceInfo,
+                message: format!("too short: {} bytes", data.len()),
+            });
+        };
+        let controller_type = ControllerType::from_id(id);
+        let mut address = [0u8; 6];
+        address.copy_from_slice(address_bytes);
+        self.request_subcommand(Subcommand::SetShipmentState, &[0x00])
+            .await?;
+        // Serial number, colors and the stick calibrations.
+        let serial_number = self.spi_flash_read(0x6000, 0x10).await?;
+        for (offset, size) in [
+            (0x6050, 0x0D),
+            (0x6080, 0x18),
+            (0x603D, 0x19),
+            (0x8010, 0x18),
+        ] {
+            self.spi_flash_read(offset, size).await?;
+        }
+        self.request_subcommand(Subcommand::SetInputReportMode, &[0x30])
+            .await?;
+        self.request_subcommand(Subcommand::TriggerButtonsElapsedTime, &[])
+            .await?;
+        self.request_subcommand(Subcommand::Enable6AxisSensor, &[0x01])
+            .await?;
+        self.request_subcommand(Subcommand::EnableVibration, &[0x01])
+            .await?;
+        self.request_subcommand(Subcommand::SetPlayerLights, &[0x01])
+            .await?;
+        Ok(HandshakeInfo {
+            controller_type,
+            address,
+            serial_number,
+        })
+    }
+
+    // Sends the subcommand and waits for the reply, skipping the other input
+    // reports in between.
+    pub async fn request_subcommand(
+        &self,
+        subcommand: Subcommand,
+        data: &[u8],
+    ) -> Result<InputReport, VirtualHostError> {
+        let mut output_report = self.output_report(OutputReportId::SubCommand);
+        output_report.set_subcommand(subcommand)?;
+        output_report.set_subcommand_data(data)?;
+        self.send(output_report)?;
+        let deadline = Instant::now() + REPLY_TIMEOUT;
+        loop {
+            let (_, report) = time::timeout_at(deadline, self.recv_report())
+                .await
+                .map_err(|_| VirtualHostError::Timeout)??;
+            if report.input_report_id() != Some(InputReportId::Standard) {
+                continue;
+            }
+            if report.response_subcommand() != Some(subcommand) {
+                return Err(VirtualHostError::UnexpectedReply {
+                    subcommand,
+                    message: format!("replied to {:?}", report.response_subcommand()),
+                });
+            }
+            if report.ack() & 0x80 == 0 {
+                return Err(VirtualHostError::UnexpectedReply {
+                    subcommand,
+                    message: format!("not acknowledged: {:#04X}", report.ack()),
+                });
+            }
+            return Ok(report);
+        }
+    }
+
+    pub async fn spi_flash_read(&self, offset: u32, size: u8) -> Result<Vec<u8>, VirtualHostError> {
+        let mut data = offset.to_le_bytes().to_vec();
+        data.push(size);
+        let reply = self
+            .request_subcommand(Subcommand::SpiFlashRead, &data)
+            .await?;
+        let data = reply.subcommand_reply_data();
+        let (Some(header), Some(read)) = (data.get(..5), data.get(5..5 + usize::from(size))) else {
+            return Err(VirtualHostError::UnexpectedReply {
+                subcommand: Subcommand::SpiFlashRead,
+                message: format!("too short for {} bytes: {} bytes", size, data.len()),
+            });
+        };
+        if header[..4] != offset.to_le_bytes() || header[4] != size {
+            return Err(VirtualHostError::UnexpectedReply {
+                subcommand: Subcommand::SpiFlashRead,
+                message: format!("read {:02X?} instead", header),
+            });
+        }
+        Ok(read.to_vec())
+    }
+
+    // Sends a rumble only output report, which the host uses to keep the
+    // connection alive.
+    pub fn send_rumble(&self) -> Result<(), VirtualHostError> {
+        self.send(self.output_report(OutputReportId::RumbleOnly))
+    }
+
+    // Receives the next input report written by the protocol, with the time
+    // it has been written.
+    pub async fn recv_report(&self) -> Result<(Instant, InputReport), VirtualHostError> {
+        let (received_at, buf) = self
+            .input_rx
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or(VirtualHostError::Closed)?;
+        Ok((
+            received_at,
+            InputReport::with_raw(BytesMut::from(&buf[..]))?,
+        ))
+    }
+
+    // Collects the input reports of the given id for the duration.
+    pub async fn collect_reports(
+        &self,
+        id: InputReportId,
+        duration: Duration,
+    ) -> Result<Vec<(Instant, InputReport)>, VirtualHostError> {
+        let mut reports = vec![];
+        let deadline = Instant::now() + duration;
+        while let Ok(res) = time::timeout_at(deadline, self.recv_report()).await {
+            let (received_at, report) = res?;
+            if report.input_report_id() == Some(id) {
+                reports.push((received_at, report));
+            }
+        }
+        Ok(reports)
+    }
+
+    // Drops the input reports received so far.
+    pub async fn drain_reports(&self) {
+        let mut input_rx = self.input_rx.lock().await;
+        while input_rx.try_recv().is_ok() {}
+    }
+
+    fn output_report(&self, id: OutputReportId) -> OutputReport {
+        let mut output_report = OutputReport::new();
+        output_report.set_output_report_id(id);
+        let mut packet_counter = self.packet_counter.lock().unwrap();
+        output_report.set_timer(*packet_counter);
+        *packet_counter += 1;
+        let mut buf = BytesMut::from(output_report.as_buf());
+        buf[3..11].copy_from_slice(&NEUTRAL_RUMBLE);
+        OutputReport::with_raw(buf).unwrap()
+    }
+
+    fn send(&self, output_report: OutputReport) -> Result<(), VirtualHostError> {
+        self.output_tx
+            .send(BytesMut::from(output_report.as_buf()))
+            .map_err(|_| VirtualHostError::Closed)
+    }
+}
+
+// In-memory transport connected to the `VirtualHost`.
+#[derive(Debug, Clone)]
+pub struct VirtualTransport {
+    inner: Arc<VirtualTransportInner>,
+}
+
+#[derive(Debug)]
+struct VirtualTransportInner {
+    output_rx: Mutex<mpsc::UnboundedReceiver<BytesMut>>,
+    input_tx: mpsc::UnboundedSender<(Instant, Bytes)>,
+    is_paused: AtomicBool,
+}
+
+impl VirtualTransport {
+    pub fn is_paused(&self) -> bool {
+        self.inner.is_paused.load(Ordering::SeqCst)
+    }
+}
+
+impl Transport for VirtualTransport {}
+
+#[async_trait]
+impl TransportRead for VirtualTransport {
+    async fn read(&self) -> std::io::Result<BytesMut> {
+        self.inner
+            .output_rx
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| std::io::ErrorKind::ConnectionReset.into())
+    }
+}
+
+#[async_trait]
+impl TransportWrite for VirtualTransport {
+    async fn write(&self, buf: Bytes) -> std::io::Result<()> {
+        self.inner
+            .input_tx
+            .send((Instant::now(), buf))
+            .map_err(|_| std::io::ErrorKind::ConnectionReset.into())
+    }
+}
+
+impl TransportPause for VirtualTransport {
+    fn pause(&self) {
+        self.inner.is_paused.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VirtualHost;
+    use crate::{
+        controller::{report::input::InputReportId, state::button::ButtonKey, ControllerType},
+        protocol::{Event, LogType, Protocol, ProtocolConfig},
+    };
+    use tokio::time::{self, Duration, Instant};
+
+    // Average interval between the reports.
+    fn average_interval(reports: &[(Instant, impl Sized)]) -> Duration {
+        assert!(reports.len() >= 2, "{} reports collected", reports.len());
+        let first = reports.first().unwrap().0;
+        let last = reports.last().unwrap().0;
+        (last - first) / (reports.len() - 1) as u32
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_to_virtual_host() {
+        let (host, transport) = VirtualHost::new();
+        let (protocol, _handle) = Protocol::connect(transport.clone(), ProtocolConfig::default())
+            .await
+            .unwrap();
+        let mut event_rx = protocol.events().await.unwrap();
+
+        let info = host.handshake().await.unwrap();
+        assert_eq!(info.controller_type, Some(ControllerType::ProController));
+        assert_eq!(info.serial_number.len(), 0x10);
+
+        // Paced at 15hz until the pairing ends.
+        host.drain_reports().await;
+        let reports = host
+            .collect_reports(InputReportId::Imu, Duration::from_millis(400))
+            .await
+            .unwrap();
+        assert!(reports.len() >= 3);
+        let interval = average_interval(&reports);
+        assert!(
+            (Duration::from_millis(60)..=Duration::from_millis(70)).contains(&interval),
+            "{interval:?}"
+        );
+
+        // Pressing A ends the pairing, then the reports are paced at 120hz.
+        protocol
+            .update_controller_state(|state| {
+                state.button_state_mut().set_button(ButtonKey::A, true)
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        time::timeout(Duration::from_secs(1), async {
+            while let Some(evt) = event_rx.recv().await {
+                if let Event::Log(LogType::PairingEnded) = evt {
+                    break;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        host.drain_reports().await;
+        let reports = host
+            .collect_reports(InputReportId::Imu, Duration::from_millis(200))
+            .await
+            .unwrap();
+        let interval = average_interval(&reports);
+        assert!(
+            (Duration::from_millis(8)..=Duration::from_millis(10)).contains(&interval),
+            "{interval:?}"
+        );
+        // Buttons: Y X B A SR SL R ZR
+        let (_, report) = reports.last().unwrap();
+        assert_eq!(report.as_buf()[4] & 0x08, 0x08);
+        assert!(!transport.is_paused());
+
+        let metrics = protocol.metrics();
+        assert!(metrics.reports_read.total() >= 10);
+        assert!(metrics.reports_written.total() > metrics.reports_read.total());
+        assert_eq!(
+            metrics.write_latency.count(),
+            metrics.reports_written.total()
+        );
+        assert!(metrics.pacing_jitter.count() > 0);
+    }
+}
@@ -1,5 +1,12 @@
 use crate::{device, session, system, transport, Address};
-use nxzr_core::{controller::ControllerType, protocol};
+use nxzr_core::{
+    capture::{self, CaptureConfig, CaptureTransport, CaptureWriter},
+    controller::{
+        color::ControllerColors, interval::SendIntervalConfig, spi_flash::SpiFlash, ControllerType,
+    },
+    protocol,
+};
+use std::sync::Arc;
 use strum::Display;
 use tokio::{
     sync::mpsc,
@@ -22,6 +29,10 @@ pub enum ConnectionError {
     SystemCommandError(#[from] system::SystemCommandError),
     #[error(transparent)]
     ProtocolError(#[from] protocol::ProtocolError),
+    #[error(transparent)]
+    CaptureError(#[from] capture::CaptureError),
+    #[error(transparent)]
+    JoinError(#[from] tokio::task::JoinError),
 }
 
 #[tracing::instrument(target = "connection")]
@@ -158,6 +169,12 @@ pub async fn establish_reconnect_connection(
 pub struct ConnectionConfig {
     pub paired_session: session::PairedSession,
     pub controller_type: ControllerType,
+    pub spi_flash: Option<SpiFlash>,
+    pub colors: Option<ControllerColors>,
+    // Pacing of the input reports, e.g. to force 60hz over weak links.
+    pub send_interval: SendIntervalConfig,
+    // Records the reports to a btsnoop file if supplied.
+    pub capture: Option<CaptureConfig>,
 }
 
 #[derive(Debug)]
@@ -175,6 +192,10 @@ impl Connection {
         let ConnectionConfig {
             paired_session,
             controller_type,
+            spi_flash,
+            colors,
+            send_interval,
+            capture,
         } = config;
         let dev_address = paired_session.dev_address;
         let reconnect = paired_session.is_reconnect;
@@ -183,16 +204,41 @@ impl Connection {
         let (transport, transport_handle) =
             transport::Transport::register(paired_session, transport::TransportConfig::default())
                 .await?;
-        let (protocol, protocol_handle) = protocol::Protocol::connect(
-            transport.clone(),
-            protocol::ProtocolConfig {
-                dev_address: dev_address.into(),
-                controller_type,
-                reconnect,
-                ..Default::default()
-            },
-        )
-        .await?;
+        let protocol_config = protocol::ProtocolConfig {
+            dev_address: dev_address.into(),
+            controller_type,
+            reconnect,
+            spi_flash,
+            colors,
+            send_interval,
+            ..Default::default()
+        };
+        let (protocol, protocol_handle) = match capture {
+            Some(capture_config) => {
+                // Rotating and creating the files may block for a while.
+                let writer = Arc::new(
+                    tokio::task::spawn_blocking(move || CaptureWriter::create(capture_config))
+                        .await??,
+                );
+                // Report the capture failure once, the connection keeps going
+                // without recording.
+                let mut error_rx = writer.watch_error();
+                tokio::spawn(async move {
+                    while error_rx.changed().await.is_ok() {
+                        if let Some(err) = &*error_rx.borrow() {
+                            tracing::warn!("stopped recording the capture: {}", err);
+                            break;
+                        }
+                    }
+                });
+                protocol::Protocol::connect(
+                    CaptureTransport::new(transport.clone(), writer),
+                    protocol_config,
+                )
+                .await?
+            }
+            None => protocol::Protocol::connect(transport.clone(), protocol_config).await?,
+        };
 
         let (close_tx, close_rx) = mpsc::channel(1);
         let (will_close_tx, will_close_rx) = mpsc::channel(1);
@@ -227,6 +273,12 @@ impl Connection {
         self.protocol.clone()
     }
 
+    // Metrics of the underlying transport, to be read along with the ones of
+    // the protocol.
+    pub fn transport_metrics(&self) -> transport::TransportMetrics {
+        self.transport.metrics()
+    }
+
     pub async fn will_close(&self) {
         self.will_close_tx.closed().await;
     }
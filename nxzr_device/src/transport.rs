@@ -3,8 +3,9 @@ use crate::session::PairedSession;
 use crate::sock::hci;
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
+use nxzr_core::metrics::{DurationHistogram, RateCounter};
 use std::future::Future;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tokio::sync::{mpsc, watch};
 use tokio::task::JoinSet;
 use tokio::time::{self, Duration};
@@ -57,6 +58,23 @@ pub struct TransportConfig {
     read_buf_size: Option<usize>,
 }
 
+// Performance metrics of the transport, see `Transport::metrics`.
+#[derive(Clone, Debug, Default)]
+pub struct TransportMetrics {
+    pub reports_read: RateCounter,
+    pub reports_written: RateCounter,
+    // Time taken by the socket to send a report.
+    pub write_latency: DurationHistogram,
+    // Time waited for a flow control permit, which is returned by the
+    // adapter once the packets are completed.
+    pub semaphore_wait: DurationHistogram,
+    // Total time the writes have been held by the write lock, i.e. while the
+    // adapter is short of the slots.
+    pub write_lock_duration: Duration,
+    // Total time the writes have waited for the transport to be resumed.
+    pub paused_duration: Duration,
+}
+
 #[derive(Debug, Clone)]
 pub struct Transport {
     inner: Arc<TransportInner>,
@@ -162,6 +180,11 @@ impl Transport {
         self.inner.pause();
     }
 
+    // Get a copy of the performance metrics collected so far.
+    pub fn metrics(&self) -> TransportMetrics {
+        self.inner.metrics.lock().unwrap().clone()
+    }
+
     pub async fn resume(&self) {
         self.inner.resume();
     }
@@ -191,6 +214,7 @@ pub(crate) struct TransportInner {
     writing_tx: watch::Sender<bool>,
     write_sem: Arc<BoundedSemaphore>,
     read_buf_size: usize,
+    metrics: Mutex<TransportMetrics>,
     closed_tx: mpsc::Sender<()>,
 }
 
@@ -237,6 +261,7 @@ impl TransportInner {
             writing_tx: watch::channel(true).0,
             write_sem: Arc::new(BoundedSemaphore::new(num_flow_control, num_flow_control)),
             read_buf_size,
+            metrics: Mutex::new(TransportMetrics::default()),
             closed_tx,
         })
     }
@@ -277,7 +302,11 @@ impl TransportInner {
         buf.resize(self.read_buf_size, 0);
         match self.session.itr_client.recv(&mut buf).await {
             Ok(0) => Err(TransportError::ReaderClosed),
-            Ok(_) => Ok(buf),
+            Ok(_) => {
+                let now = time::Instant::now();
+                self.metrics.lock().unwrap().reports_read.record(now);
+                Ok(buf)
+            }
             Err(err) => Err(err.into()),
         }
     }
@@ -286,14 +315,36 @@ impl TransportInner {
         if self.is_closed() {
             return Err(TransportError::OperationWhileClosed);
         }
+        let is_paused = !*self.running_tx.borrow();
+        let paused_at = time::Instant::now();
         self.running().await;
+        let acquiring_at = time::Instant::now();
         self.write_sem.acquire_forget().await?;
+        let is_locked = !*self.writing_tx.borrow();
+        let locked_at = time::Instant::now();
         self.writable().await;
+        let sending_at = time::Instant::now();
+        {
+            let mut metrics = self.metrics.lock().unwrap();
+            if is_paused {
+                metrics.paused_duration += acquiring_at - paused_at;
+            }
+            metrics.semaphore_wait.record(locked_at - acquiring_at);
+            if is_locked {
+                metrics.write_lock_duration += sending_at - locked_at;
+            }
+        }
         // Writing a buffer in length more than MTU may fail, however, L2CAP's
         // [SeqPacket] socket seems allows writing buf regardless of the MTU length.
         match self.session.itr_client.send(&buf).await {
             Ok(0) => Err(TransportError::WriterClosed),
-            Ok(_) => Ok(()),
+            Ok(_) => {
+                let now = time::Instant::now();
+                let mut metrics = self.metrics.lock().unwrap();
+                metrics.write_latency.record(now - sending_at);
+                metrics.reports_written.record(now);
+                Ok(())
+            }
             Err(err) => Err(err.into()),
         }
     }
@@ -87,6 +87,7 @@ impl From<InputUpdatePayload> for ControlStreamRequest {
             imu_pos: payload
                 .imu_position
                 .map(|pos| Position { x: pos.x, y: pos.y }),
+            power_state: None,
         }
     }
 }
@@ -188,7 +189,7 @@ impl AgentManager {
             async move {
                 let _shutdown_guard = shutdown.drop_guard();
                 let mut stream = client
-                    .connect_switch(Request::new(ConnectSwitchRequest {}))
+                    .connect_switch(Request::new(ConnectSwitchRequest::default()))
                     .await?
                     .into_inner();
                 while let Some(response) = stream.message().await? {
@@ -1,18 +1,16 @@
-use nxzr_core::{controller::state::button::ButtonKey, protocol::Protocol};
+use nxzr_core::{
+    controller::state::button::ButtonKey,
+    protocol::{Hold, InputSequence, Protocol},
+};
 use std::sync::Arc;
-use tokio::time::{self, Duration};
+use tokio::time::Duration;
+
+// How long to hold the button for, regardless of the send interval.
+const KEY_PRESS_DURATION: Duration = Duration::from_millis(100);
 
 pub async fn key_press(protocol: Protocol, key: ButtonKey) -> anyhow::Result<()> {
     protocol
-        .update_controller_state(|state| {
-            state.button_state_mut().set_button(key, true).unwrap();
-        })
-        .await?;
-    time::sleep(Duration::from_millis(100)).await;
-    protocol
-        .update_controller_state(|state| {
-            state.button_state_mut().set_button(key, false).unwrap();
-        })
+        .send_sequence(InputSequence::new().press(key, Hold::Duration(KEY_PRESS_DURATION)))
         .await?;
     Ok(())
 }
@@ -1,12 +1,13 @@
 use anyhow::Ok;
 use clap::{Parser, Subcommand};
+use nxzr_core::{capture::CaptureConfig, controller::interval::SendIntervalConfig};
 use nxzr_device::{
     device::{self, DeviceConfig},
     system,
 };
 use nxzr_shared::shutdown::Shutdown;
-use service::NxzrService;
-use std::{future::Future, net::ToSocketAddrs, sync::Arc};
+use service::{NxzrService, NxzrServiceConfig};
+use std::{future::Future, net::ToSocketAddrs, path::PathBuf, sync::Arc, time::Duration};
 use tokio::{signal, sync::mpsc};
 use tracing_subscriber::prelude::*;
 
@@ -23,7 +24,31 @@ struct Cli {
 #[derive(Subcommand)]
 enum Cmd {
     /// Run server daemon
-    Run,
+    Run {
+        /// Path to the SPI flash image (.bin) to use as a controller profile.
+        /// Changes made by the Switch are saved back to the file.
+        #[arg(long)]
+        spi_flash: Option<PathBuf>,
+        /// Creates the SPI flash image with the default contents if it does
+        /// not exist, instead of failing.
+        #[arg(long, requires = "spi_flash")]
+        create_spi_flash: bool,
+        /// Rate in Hz of the full input reports, which overrides the one of
+        /// the controller type, e.g. 60 to keep the Pro Controller at 60Hz
+        /// over weak links.
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..=1000))]
+        full_report_rate: Option<u32>,
+        /// Path to the btsnoop file to record the reports of each connection,
+        /// which can be opened with Wireshark.
+        #[arg(long)]
+        capture: Option<PathBuf>,
+        /// Size in bytes at which the capture file is rotated.
+        #[arg(long, default_value_t = 16 * 1024 * 1024)]
+        capture_max_size: u64,
+        /// Number of the rotated capture files to keep.
+        #[arg(long, default_value_t = 4)]
+        capture_max_files: usize,
+    },
     /// Run system integrity check
     Check,
 }
@@ -47,13 +72,36 @@ async fn main() -> anyhow::Result<()> {
     // Run CLI.
     let args = Cli::parse();
     match args.command {
-        Cmd::Run => {
+        Cmd::Run {
+            spi_flash,
+            create_spi_flash,
+            full_report_rate,
+            capture,
+            capture_max_size,
+            capture_max_files,
+        } => {
             tracing::info!("running daemon...");
             // Checks for system requirements.
             system::check_privileges().await?;
             system::check_system_requirements().await?;
             // Then, runs the actual service.
-            run(signal::ctrl_c()).await?
+            run(
+                signal::ctrl_c(),
+                NxzrServiceConfig {
+                    spi_flash_path: spi_flash,
+                    create_spi_flash,
+                    send_interval: SendIntervalConfig {
+                        full_report_interval: full_report_rate
+                            .map(|rate| Duration::from_secs_f64(1.0 / f64::from(rate))),
+                    },
+                    capture: capture.map(|path| CaptureConfig {
+                        path,
+                        max_file_size: capture_max_size,
+                        max_files: capture_max_files,
+                    }),
+                },
+            )
+            .await?
         }
         Cmd::Check => {
             tracing::info!("running system check...");
@@ -66,7 +114,7 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-pub async fn run(shutdown: impl Future) -> anyhow::Result<()> {
+pub async fn run(shutdown: impl Future, service_config: NxzrServiceConfig) -> anyhow::Result<()> {
     let (shutdown_tx, shutdown_rx) = mpsc::channel(1);
     let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel(1);
     let shutdown_token = Shutdown::new(shutdown_tx, shutdown_complete_tx.clone());
@@ -93,7 +141,8 @@ pub async fn run(shutdown: impl Future) -> anyhow::Result<()> {
         let shutdown_token = shutdown_token.clone();
         async move {
             let _shutdown_guard = shutdown_token.drop_guard();
-            let nxzr_service = NxzrService::new(device, shutdown_token.clone()).await?;
+            let nxzr_service =
+                NxzrService::new(device, shutdown_token.clone(), service_config).await?;
             let svc = nxzr_proto::nxzr_server::NxzrServer::new(nxzr_service);
             tonic::transport::Server::builder()
                 .add_service(svc)
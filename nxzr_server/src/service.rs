@@ -1,5 +1,13 @@
 use nxzr_core::{
-    controller::{self, state::button::ButtonKey},
+    capture::CaptureConfig,
+    controller::{
+        self,
+        color::{ControllerColors, Rgb},
+        interval::SendIntervalConfig,
+        rumble::{RumbleBand, RumbleData},
+        spi_flash::{SpiFlash, SpiFlashError},
+        state::{button::ButtonKey, imu::Vector3},
+    },
     protocol,
 };
 use nxzr_device::{connection, device, session};
@@ -8,19 +16,30 @@ use nxzr_proto::{
     ConnectSwitchResponse, ConnectionEvent, ConnectionMetadata, ControlStreamRequest,
     ControlStreamResponse, Error as ProtoError, GetDeviceStatusRequest, GetDeviceStatusResponse,
     GetProtocolStateRequest, GetProtocolStateResponse, ReconnectSwitchRequest,
-    ReconnectSwitchResponse,
+    ReconnectSwitchResponse, Warning as ProtoWarning,
 };
 use nxzr_shared::shutdown::Shutdown;
 use std::{
+    path::PathBuf,
     pin::Pin,
     str::FromStr,
     sync::{Arc, Mutex},
     time::SystemTime,
 };
-use tokio::sync::mpsc;
+use tokio::{
+    sync::mpsc,
+    time::{self, Duration},
+};
 use tokio_stream::{wrappers::UnboundedReceiverStream, Stream, StreamExt};
 use tonic::{async_trait, Request, Response, Status, Streaming};
 
+// Scale of the IMU position sent by the clients, which is treated as the
+// angular velocity in radians per second when divided by it.
+const IMU_POSITION_SENSITIVITY: f32 = 8000.0;
+const RADIANS_TO_DEGREES: f32 = 57.3;
+// Delay to batch the writes to the SPI flash before saving the profile.
+const SPI_FLASH_SAVE_DELAY: Duration = Duration::from_secs(1);
+
 type ServiceResult<T> = Result<Response<T>, Status>;
 type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
 
@@ -42,6 +61,10 @@ pub enum NxzrServiceError {
     ConnectionError(#[from] connection::ConnectionError),
     #[error(transparent)]
     ProtocolError(#[from] protocol::ProtocolError),
+    #[error(transparent)]
+    SpiFlashError(#[from] SpiFlashError),
+    #[error(transparent)]
+    JoinError(#[from] tokio::task::JoinError),
 }
 
 impl From<NxzrServiceError> for Status {
@@ -50,10 +73,26 @@ impl From<NxzrServiceError> for Status {
     }
 }
 
+#[derive(Debug, Default)]
+pub struct NxzrServiceConfig {
+    // Path to the SPI flash image used as a controller profile.
+    pub spi_flash_path: Option<PathBuf>,
+    // Creates the profile with the default image if the file does not exist,
+    // otherwise a missing file is an error.
+    pub create_spi_flash: bool,
+    // Pacing of the input reports of each connection.
+    pub send_interval: SendIntervalConfig,
+    // Records the reports of each connection if supplied.
+    pub capture: Option<CaptureConfig>,
+}
+
 #[derive(Debug)]
 pub struct NxzrService {
     device: Arc<device::Device>,
     conn_state: Arc<Mutex<ConnectionState>>,
+    spi_flash_path: Option<PathBuf>,
+    send_interval: SendIntervalConfig,
+    capture: Option<CaptureConfig>,
     shutdown: Shutdown,
 }
 
@@ -66,10 +105,26 @@ enum ConnectionState {
 }
 
 impl NxzrService {
-    pub async fn new(device: Arc<device::Device>, shutdown: Shutdown) -> anyhow::Result<Self> {
+    pub async fn new(
+        device: Arc<device::Device>,
+        shutdown: Shutdown,
+        config: NxzrServiceConfig,
+    ) -> anyhow::Result<Self> {
+        // Validate the profile before accepting any connections.
+        if let Some(path) = &config.spi_flash_path {
+            if config.create_spi_flash && !path.exists() {
+                SpiFlash::new().save(path)?;
+                tracing::info!("created SPI flash profile: {}", path.display());
+            }
+            SpiFlash::load(path)?;
+            tracing::info!("using SPI flash profile: {}", path.display());
+        }
         Ok(Self {
             device,
             conn_state: Arc::new(Mutex::new(ConnectionState::NotConnected)),
+            spi_flash_path: config.spi_flash_path,
+            send_interval: config.send_interval,
+            capture: config.capture,
             shutdown,
         })
     }
@@ -105,8 +160,14 @@ impl Nxzr for NxzrService {
     #[tracing::instrument(target = "service")]
     async fn connect_switch(
         &self,
-        _req: Request<ConnectSwitchRequest>,
+        req: Request<ConnectSwitchRequest>,
     ) -> ServiceResult<Self::ConnectSwitchStream> {
+        let colors = req.into_inner().colors.map(|colors| ControllerColors {
+            body: Rgb::from_u32(colors.body),
+            buttons: Rgb::from_u32(colors.buttons),
+            left_grip: colors.left_grip.map(Rgb::from_u32),
+            right_grip: colors.right_grip.map(Rgb::from_u32),
+        });
         // Start connection.
         {
             let mut guard = self.conn_state.lock().unwrap();
@@ -125,9 +186,19 @@ impl Nxzr for NxzrService {
             let shutdown = self.shutdown.clone();
             let device = self.device.clone();
             let conn_state = self.conn_state.clone();
+            let spi_flash_path = self.spi_flash_path.clone();
+            let send_interval = self.send_interval.clone();
+            let capture = self.capture.clone();
             async move {
                 let _shutdown_guard = shutdown.drop_guard();
-                let connect_switch_fut = handle_connect_switch(device, stream_tx.clone());
+                let connect_switch_fut = handle_connect_switch(
+                    device,
+                    spi_flash_path,
+                    colors,
+                    send_interval,
+                    capture,
+                    stream_tx.clone(),
+                );
                 let res = tokio::select! {
                     res = connect_switch_fut => Some(res),
                     _ = stream_tx.closed() => None,
@@ -257,9 +328,26 @@ impl Nxzr for NxzrService {
     #[tracing::instrument(target = "service")]
     async fn get_protocol_state(
         &self,
-        req: Request<GetProtocolStateRequest>,
+        _req: Request<GetProtocolStateRequest>,
     ) -> ServiceResult<GetProtocolStateResponse> {
-        unimplemented!()
+        let conn = {
+            let guard = self.conn_state.lock().unwrap();
+            let ConnectionState::Connected(conn) = &*guard else {
+                return Err(NxzrServiceError::NotConnected.into());
+            };
+            conn.clone()
+        };
+        let protocol = conn.protocol();
+        let snapshot = protocol.snapshot();
+        Ok(Response::new(GetProtocolStateResponse {
+            is_pairing: snapshot.is_pairing,
+            send_interval: snapshot.send_interval,
+            report_mode: snapshot.report_mode.map(u32::from),
+            connected_at: snapshot
+                .connected_at
+                .map(|connected_at| (SystemTime::now() - connected_at.elapsed()).into()),
+            controller_state_dump: format!("{:?}", protocol.controller_state()),
+        }))
     }
 
     type ControlStreamStream = ResponseStream<ControlStreamResponse>;
@@ -319,11 +407,21 @@ async fn handle_control_report(
                 r_stick.set_horizontal_scale(right_stick_pos.x)?;
                 r_stick.set_vertical_scale(right_stick_pos.y)?;
             }
-            // Handle IMU state.
+            // Handle IMU state, the position is mapped to the angular velocity
+            // of yaw (horizontal) and pitch (vertical).
             if let Some(imu_pos) = control_req.imu_pos {
-                let imu = state.imu_state_mut();
-                imu.set_horizontal(imu_pos.x as u16);
-                imu.set_vertical(imu_pos.y as u16);
+                state.imu_state_mut().set_gyro(Vector3::new(
+                    0.0,
+                    imu_pos.y / IMU_POSITION_SENSITIVITY * RADIANS_TO_DEGREES,
+                    -imu_pos.x / IMU_POSITION_SENSITIVITY * RADIANS_TO_DEGREES,
+                ))?;
+            }
+            // Handle power state.
+            if let Some(power_state) = control_req.power_state {
+                let power = state.power_state_mut();
+                power.set_battery_level(power_state.battery_level.try_into()?)?;
+                power.set_charging(power_state.charging);
+                power.set_powered(power_state.powered);
             }
             anyhow::Ok(())
         })
@@ -336,6 +434,10 @@ async fn handle_control_report(
 
 async fn handle_connect_switch(
     device: Arc<device::Device>,
+    spi_flash_path: Option<PathBuf>,
+    colors: Option<ControllerColors>,
+    send_interval: SendIntervalConfig,
+    capture: Option<CaptureConfig>,
     stream_tx: mpsc::UnboundedSender<Result<ConnectSwitchResponse, Status>>,
 ) -> Result<(connection::Connection, connection::ConnectionHandle), NxzrServiceError> {
     // Send Event: Connecting
@@ -352,6 +454,10 @@ async fn handle_connect_switch(
     }));
 
     let controller_type = controller::ControllerType::ProController;
+    let spi_flash = match &spi_flash_path {
+        Some(path) => Some(load_spi_flash_profile(path.clone()).await?),
+        None => None,
+    };
     let session_listener = connection::create_session_listener(&device).await?;
     let paired_session =
         connection::establish_initial_connection(&device, &session_listener, controller_type)
@@ -361,17 +467,37 @@ async fn handle_connect_switch(
     let (conn, conn_handle) = connection::Connection::run(connection::ConnectionConfig {
         paired_session,
         controller_type,
+        spi_flash,
+        colors,
+        send_interval,
+        capture,
     })
     .await?;
 
     // Listen for protocol events.
     tokio::spawn({
         let stream_tx = stream_tx.clone();
-        let mut event_rx = conn.protocol().events().await?;
+        let protocol = conn.protocol();
+        let mut event_rx = protocol.events().await?;
+        let save_tx = spi_flash_path.map(|path| spawn_spi_flash_saver(path, protocol));
         async move {
             while let Some(evt) = event_rx.recv().await {
-                // Log to the tracing stream as well as gRPC responses.
-                tracing::info!("protocol event: {}", &evt.to_string());
+                // Log to the tracing stream as well as gRPC responses. Rumble
+                // and lights are sent many times per second while playing.
+                match &evt {
+                    protocol::Event::Rumble(_)
+                    | protocol::Event::PlayerLights(_)
+                    | protocol::Event::HomeLight(_) => {
+                        tracing::trace!("protocol event: {}", &evt.to_string())
+                    }
+                    _ => tracing::info!("protocol event: {}", &evt.to_string()),
+                }
+                // Persist the changes made by the Switch to the profile.
+                if let protocol::Event::Log(protocol::LogType::SpiFlashWritten { .. }) = &evt {
+                    if let Some(save_tx) = &save_tx {
+                        let _ = save_tx.try_send(());
+                    }
+                }
                 // We limit only few events to be actually sent over the
                 // wire, for example, protocol's `Closed` event is
                 // ignored as we need to handle it after a cleanup.
@@ -401,6 +527,34 @@ async fn handle_connect_switch(
     Ok((conn, conn_handle))
 }
 
+// Loads the SPI flash profile off the runtime, as the image is 512KB.
+async fn load_spi_flash_profile(path: PathBuf) -> Result<SpiFlash, NxzrServiceError> {
+    Ok(tokio::task::spawn_blocking(move || SpiFlash::load(path)).await??)
+}
+
+// Saves the SPI flash image to the profile when notified, until the sender is
+// dropped. The Switch writes in small chunks, so the writes made within
+// `SPI_FLASH_SAVE_DELAY` are saved at once.
+fn spawn_spi_flash_saver(path: PathBuf, protocol: protocol::Protocol) -> mpsc::Sender<()> {
+    let (save_tx, mut save_rx) = mpsc::channel(1);
+    tokio::spawn(async move {
+        while save_rx.recv().await.is_some() {
+            time::sleep(SPI_FLASH_SAVE_DELAY).await;
+            while save_rx.try_recv().is_ok() {}
+            let Some(spi_flash) = protocol.spi_flash() else {
+                continue;
+            };
+            let path = path.clone();
+            match tokio::task::spawn_blocking(move || spi_flash.save(path)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => tracing::warn!("failed to save SPI flash profile: {}", err),
+                Err(err) => tracing::warn!("failed to save SPI flash profile: {}", err),
+            }
+        }
+    });
+    save_tx
+}
+
 fn map_protocol_event_to_event_kind(
     protocol_event: protocol::Event,
 ) -> Option<connection_event::Kind> {
@@ -414,7 +568,43 @@ fn map_protocol_event_to_event_kind(
                 kind: connection_event::EventLogKind::SubcommandReceived.into(),
                 message: format!("Subcommand received: {}", subcommand),
             },
-            _ => return None,
+            protocol::LogType::ReportModeChanged(mode) => connection_event::EventLog {
+                kind: connection_event::EventLogKind::ReportModeChanged.into(),
+                message: format!("Input report mode changed: {:#04X}", mode),
+            },
+            protocol::LogType::SendIntervalChanged(interval) => connection_event::EventLog {
+                kind: connection_event::EventLogKind::SendIntervalChanged.into(),
+                message: match interval {
+                    Some(interval) => format!("Send interval changed: {:?}", interval),
+                    None => "Send interval changed: waiting for updates.".into(),
+                },
+            },
+            protocol::LogType::ImuToggled { enabled } => connection_event::EventLog {
+                kind: connection_event::EventLogKind::ImuToggled.into(),
+                message: format!("IMU enabled: {}", enabled),
+            },
+            protocol::LogType::VibrationToggled { enabled } => connection_event::EventLog {
+                kind: connection_event::EventLogKind::VibrationToggled.into(),
+                message: format!("Vibration enabled: {}", enabled),
+            },
+            protocol::LogType::McuModeChanged(mode) => connection_event::EventLog {
+                kind: connection_event::EventLogKind::McuModeChanged.into(),
+                message: format!("MCU mode changed: {:?}", mode),
+            },
+            // Lagged writes are reported as warnings, as they were before
+            // having their own log type.
+            protocol::LogType::WritesLagged(stats) => {
+                return Some(connection_event::Kind::Warning(ProtoWarning {
+                    message: format!(
+                        "Writes lagged behind the send interval by {:?} (max: {:?}, count: {}).",
+                        stats.lag, stats.max_lag, stats.count
+                    ),
+                    timestamp: Some(SystemTime::now().into()),
+                    ..Default::default()
+                }))
+            }
+            // Persisted by the caller instead.
+            protocol::LogType::SpiFlashWritten { .. } => return None,
         })),
         protocol::Event::Error(err) => Some(connection_event::Kind::Error(ProtoError {
             message: err.to_string(),
@@ -426,5 +616,24 @@ fn map_protocol_event_to_event_kind(
             timestamp: Some(SystemTime::now().into()),
             ..Default::default()
         })),
+        protocol::Event::Rumble(rumble) => {
+            Some(connection_event::Kind::Rumble(connection_event::Rumble {
+                left: Some(map_rumble_data(rumble.left)),
+                right: Some(map_rumble_data(rumble.right)),
+            }))
+        }
+        // Lights are not forwarded to the clients at the moment.
+        protocol::Event::PlayerLights(_) | protocol::Event::HomeLight(_) => None,
+    }
+}
+
+fn map_rumble_data(data: RumbleData) -> connection_event::RumbleData {
+    let map_band = |band: RumbleBand| connection_event::RumbleBand {
+        frequency: band.frequency,
+        amplitude: band.amplitude,
+    };
+    connection_event::RumbleData {
+        high_band: Some(map_band(data.high_band)),
+        low_band: Some(map_band(data.low_band)),
     }
 }